@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+// the cli takes a command per run, the repl one per line, they don't have the same commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Cli,
+    Repl
+}
+
+#[derive(Debug)]
+pub struct Flag {
+    // without the dashes, given as --long value or --long=value
+    pub long: &'static str,
+    pub short: Option<char>,
+    // name of the value in usage, none for switches which take no value
+    pub value: Option<&'static str>,
+    pub default: Option<&'static str>,
+    pub required: bool,
+    pub help: &'static str
+}
+
+const fn switch(long:&'static str, short:Option<char>, help:&'static str) -> Flag {
+    Flag{ long, short, value: None, default: None, required: false, help }
+}
+
+const fn option(long:&'static str, short:Option<char>, value:&'static str, default:Option<&'static str>, help:&'static str) -> Flag {
+    Flag{ long, short, value: Some(value), default, required: false, help }
+}
+
+const fn required(long:&'static str, short:Option<char>, value:&'static str, help:&'static str) -> Flag {
+    Flag{ long, short, value: Some(value), default: None, required: true, help }
+}
+
+#[derive(Debug)]
+pub struct Positional {
+    pub name: &'static str,
+    // takes every positional argument left
+    pub many: bool,
+    pub optional: bool,
+    pub help: &'static str
+}
+
+const fn positional(name:&'static str, help:&'static str) -> Positional {
+    Positional{ name, many: false, optional: false, help }
+}
+
+#[derive(Debug)]
+pub struct Command {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub mode: Mode,
+    pub positionals: &'static [Positional],
+    pub flags: &'static [Flag],
+    pub help: &'static str
+}
+
+const MODEL: Flag = required("model", Some('m'), "model", "model file to use");
+const OUTPUT: Flag = option("output", Some('o'), "path", None, "where to write the result, stdout if not given or -");
+const MODEL_OUTPUT: Flag = option("output", Some('o'), "path", None, "where to write the model, stdout if not given or -");
+const FORMAT: Flag = option("format", None, "text|binary|minbpe|tiktoken", None, "model format, by the output's extension if not given: .bin binary, .tiktoken a rank file, text otherwise. minbpe's only when given");
+const RESUME: Flag = option("resume", None, "model", None, "keep training this model, its ids stay the same");
+const VOCAB_SIZE: Flag = option("vocab-size", None, "n", Some("512"), "number of tokens to train up to, special tokens not counted");
+const TYPE: Flag = option("type", None, "basic|regex", None, "tokenizer to train, regex if a pattern is given, basic otherwise");
+const PATTERN: Flag = option("pattern", None, "gpt2|gpt4|regex", None, "split pattern of a regex tokenizer, gpt4 if only --type regex is given");
+const SPECIAL: Flag = option("special", None, "token", None, "special token registered after the merges, repeat for more");
+const META: Flag = option("meta", None, "key=value", None, "saved with the model, repeat for more");
+const MIN_FREQUENCY: Flag = option("min-frequency", None, "n", Some("1"), "stop once no pair occurs this often");
+const THREADS: Flag = option("threads", None, "n", None, "threads to count and merge with, every core if not given. merges with fewer than 65536 occurrences run on one thread, the merges come out the same either way");
+const MEMORY_LIMIT: Flag = option("memory-limit", None, "mb", Some("1024"), "megabytes the chunk table may take before it's spilled to disk");
+const SPILL_DIR: Flag = option("spill-dir", None, "dir", None, "where spilled chunk tables go, the system temp dir if not given");
+const INCLUDE: Flag = option("include", None, "exts", None, "only train on files with these comma separated extensions in directories and patterns");
+const EXCLUDE: Flag = option("exclude", None, "exts", None, "skip files with these comma separated extensions in directories and patterns");
+const ALLOWED_SPECIAL: Flag = option("allowed-special", None, "all|none|raise|tokens", Some("raise"), "special tokens in the text become their ids (all), plain text (none), an error (raise), or only the comma separated tokens given do");
+const LENIENT: Flag = switch("lenient", None, "skip model entries that don't parse, each one is printed as a warning");
+const QUIET: Flag = switch("quiet", Some('q'), "don't print every merge while training");
+
+const CORPUS: Positional = Positional{ name: "corpus", many: true, optional: false, help: "files, directories or patterns like ./data/**/*.txt" };
+const TOPIC: Positional = Positional{ name: "command", many: false, optional: true, help: "command to show help for" };
+
+pub const COMMANDS: &[Command] = &[
+    Command{ name: "encode", aliases: &["e", "enc"], mode: Mode::Cli,
+        positionals: &[Positional{ name: "input", many: false, optional: true, help: "text file to encode, stdin if not given or -" }], flags: &[MODEL, OUTPUT, ALLOWED_SPECIAL, LENIENT],
+        help: "encode a text file into comma separated token ids" },
+    Command{ name: "decode", aliases: &["d", "dec"], mode: Mode::Cli,
+        positionals: &[Positional{ name: "input", many: false, optional: true, help: "file of comma separated token ids, stdin if not given or -" }], flags: &[MODEL, OUTPUT, LENIENT],
+        help: "decode token ids back into text" },
+    Command{ name: "train", aliases: &["t", "tr"], mode: Mode::Cli,
+        positionals: &[CORPUS], flags: &[MODEL_OUTPUT, FORMAT, RESUME, VOCAB_SIZE, TYPE, PATTERN, SPECIAL, META, MIN_FREQUENCY, THREADS, MEMORY_LIMIT, SPILL_DIR, INCLUDE, EXCLUDE, QUIET],
+        help: "train a model on a corpus" },
+    Command{ name: "check", aliases: &["ck"], mode: Mode::Cli,
+        positionals: &[positional("model", "model file to check")], flags: &[LENIENT],
+        help: "check that a model's merges, vocab and special tokens agree, every problem is listed" },
+    Command{ name: "help", aliases: &["h"], mode: Mode::Cli,
+        positionals: &[TOPIC], flags: &[],
+        help: "show usage, or the flags of one command. without a command the repl starts" },
+
+    Command{ name: "encode", aliases: &["e", "enc"], mode: Mode::Repl,
+        positionals: &[positional("input", "text file to encode")], flags: &[ALLOWED_SPECIAL],
+        help: "encode a text file with the current model" },
+    Command{ name: "decode", aliases: &["d", "dec"], mode: Mode::Repl,
+        positionals: &[positional("input", "file of comma separated token ids")], flags: &[],
+        help: "decode token ids with the current model" },
+    Command{ name: "train", aliases: &["t", "tr"], mode: Mode::Repl,
+        positionals: &[CORPUS], flags: &[VOCAB_SIZE, TYPE, PATTERN, SPECIAL, META, MIN_FREQUENCY, THREADS, MEMORY_LIMIT, SPILL_DIR, INCLUDE, EXCLUDE, QUIET],
+        help: "train a new model on a corpus, patterns with spaces only work as gpt2/gpt4 here" },
+    Command{ name: "continue", aliases: &["c", "cont"], mode: Mode::Repl,
+        positionals: &[CORPUS], flags: &[VOCAB_SIZE, SPECIAL, META, MIN_FREQUENCY, THREADS, MEMORY_LIMIT, SPILL_DIR, INCLUDE, EXCLUDE, QUIET],
+        help: "keep training the current model up to --vocab-size" },
+    Command{ name: "load", aliases: &["l", "ld"], mode: Mode::Repl,
+        positionals: &[positional("model", "model file to load")], flags: &[LENIENT],
+        help: "load a model" },
+    Command{ name: "save", aliases: &["s", "sv"], mode: Mode::Repl,
+        positionals: &[positional("model", "where to write the model, minbpe's format also writes a .vocab next to it")], flags: &[FORMAT],
+        help: "save the current model" },
+    Command{ name: "special", aliases: &["sp"], mode: Mode::Repl,
+        positionals: &[Positional{ name: "token", many: true, optional: false, help: "special tokens like <|endoftext|>" }], flags: &[],
+        help: "register special tokens after the merges" },
+    Command{ name: "print", aliases: &["p", "pr"], mode: Mode::Repl,
+        positionals: &[], flags: &[],
+        help: "print the current model" },
+    Command{ name: "help", aliases: &["h"], mode: Mode::Repl,
+        positionals: &[TOPIC], flags: &[],
+        help: "show the commands, or the flags of one command" },
+];
+
+#[derive(Debug)]
+pub enum Invocation {
+    Run(Parsed),
+    Help(String)
+}
+
+#[derive(Debug)]
+pub struct Parsed {
+    pub command: &'static Command,
+    pub positionals: Vec<String>,
+    values: HashMap<&'static str, Vec<String>>
+}
+
+impl Parsed {
+    // the last value given for the flag, or its default
+    pub fn value(&self, long:&str) -> Option<&str> {
+        match self.values.get(long).and_then(|values| values.last()) {
+            Some(value) => Some(value),
+            None => self.command.flags.iter().find(|flag| flag.long == long).and_then(|flag| flag.default)
+        }
+    }
+
+    // every value given for a repeatable flag
+    pub fn values(&self, long:&str) -> &[String] {
+        self.values.get(long).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn switch(&self, long:&str) -> bool {
+        self.values.contains_key(long)
+    }
+}
+
+// args start with the command name, leading dashes on it are ignored so -e and --encode work too
+pub fn parse(args:&[&str], mode:Mode) -> Result<Invocation, String> {
+    let Some(name) = args.first() else {
+        return Ok(Invocation::Help(usage(mode)));
+    };
+    let command = find(name.trim_start_matches('-'), mode)
+        .ok_or(format!("Unknown command {}\n{}", name, usage(mode)))?;
+    if command.name == "help" {
+        return match args.get(1) {
+            Some(topic) => find(topic.trim_start_matches('-'), mode).map(|command| Invocation::Help(command_usage(command)))
+                .ok_or(format!("Unknown command {}\n{}", topic, usage(mode))),
+            None => Ok(Invocation::Help(usage(mode)))
+        };
+    }
+
+    let mut parsed = Parsed{ command, positionals: Vec::new(), values: HashMap::new() };
+    let mut rest = args[1..].iter();
+    let mut flags_done = false;
+    while let Some(arg) = rest.next() {
+        if flags_done || !arg.starts_with('-') || *arg == "-" {
+            parsed.positionals.push(arg.to_string());
+            continue;
+        }
+        if *arg == "--" {
+            flags_done = true;
+            continue;
+        }
+        if *arg == "-h" || *arg == "--help" {
+            return Ok(Invocation::Help(command_usage(command)));
+        }
+
+        let (key, inline) = match arg.strip_prefix("--") {
+            Some(long) => match long.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (long, None)
+            },
+            None => (&arg[1..], None)
+        };
+        let flag = command.flags.iter()
+            .find(|flag| flag.long == key || (key.chars().count() == 1 && flag.short == key.chars().next()))
+            .ok_or(format!("Unknown flag {} for {}, see help {}", arg, command.name, command.name))?;
+        let value = match (flag.value, inline) {
+            (None, None) => String::new(),
+            (None, Some(_)) => return Err(format!("Flag --{} takes no value", flag.long)),
+            (Some(_), Some(value)) => value.to_string(),
+            (Some(name), None) => rest.next().ok_or(format!("Missing <{}> for --{}", name, flag.long))?.to_string()
+        };
+        parsed.values.entry(flag.long).or_default().push(value);
+    }
+
+    let takes = command.positionals.iter().filter(|p| !p.optional).count();
+    let many = command.positionals.iter().any(|p| p.many);
+    if parsed.positionals.len() < takes {
+        let missing = &command.positionals[parsed.positionals.len()];
+        return Err(format!("Missing <{}> for {}\n{}", missing.name, command.name, command_usage(command)));
+    }
+    if !many && parsed.positionals.len() > command.positionals.len() {
+        return Err(format!("Too many arguments for {}: {}\n{}", command.name, parsed.positionals[command.positionals.len()..].join(" "), command_usage(command)));
+    }
+    if let Some(flag) = command.flags.iter().find(|flag| flag.required && !parsed.values.contains_key(flag.long)) {
+        return Err(format!("Missing --{} for {}\n{}", flag.long, command.name, command_usage(command)));
+    }
+    Ok(Invocation::Run(parsed))
+}
+
+fn find(name:&str, mode:Mode) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.mode == mode && (command.name == name || command.aliases.contains(&name)))
+}
+
+// every command of the mode on one line each
+pub fn usage(mode:Mode) -> String {
+    let mut out = match mode {
+        Mode::Cli => "cli usage: ./app <command> [flags], ./app alone starts the repl\n".to_string(),
+        Mode::Repl => "repl commands:\n".to_string()
+    };
+    for command in COMMANDS.iter().filter(|command| command.mode == mode) {
+        out.push_str(&format!("\t{}\n\t\t{}\n", synopsis(command), command.help));
+    }
+    out.push_str("\thelp <command> shows its flags");
+    if mode == Mode::Cli {
+        out.push_str("\nexit codes: 2 bad arguments, 3 unreadable input or unwritable output, 4 bad model or a failed check, 5 ids that don't decode, 6 text that doesn't encode");
+    }
+    out
+}
+
+pub fn command_usage(command:&Command) -> String {
+    let mut out = format!("{}\n\t{}\n", synopsis(command), command.help);
+    for p in command.positionals {
+        out.push_str(&format!("\t<{}>{}\n\t\t{}\n", p.name, if p.many { "..." } else { "" }, p.help));
+    }
+    for flag in command.flags {
+        let short = flag.short.map_or(String::new(), |short| format!("-{}, ", short));
+        let value = flag.value.map_or(String::new(), |value| format!(" <{}>", value));
+        let default = flag.default.map_or(String::new(), |default| format!(" (default {})", default));
+        out.push_str(&format!("\t{}--{}{}\n\t\t{}{}\n", short, flag.long, value, flag.help, default));
+    }
+    out.pop();
+    out
+}
+
+// like "train|t|tr <corpus>... [-o <path>] ..."
+fn synopsis(command:&Command) -> String {
+    let mut out = [&[command.name], command.aliases].concat().join("|");
+    for p in command.positionals {
+        let name = format!("<{}>{}", p.name, if p.many { "..." } else { "" });
+        out.push_str(&if p.optional { format!(" [{}]", name) } else { format!(" {}", name) });
+    }
+    for flag in command.flags {
+        let name = match (flag.short, flag.value) {
+            (Some(short), Some(value)) => format!("-{} <{}>", short, value),
+            (Some(short), None) => format!("-{}", short),
+            (None, Some(value)) => format!("--{} <{}>", flag.long, value),
+            (None, None) => format!("--{}", flag.long)
+        };
+        out.push_str(&if flag.required { format!(" {}", name) } else { format!(" [{}]", name) });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args:&[&str], mode:Mode) -> Result<Parsed, String> {
+        match parse(args, mode)? {
+            Invocation::Run(parsed) => Ok(parsed),
+            Invocation::Help(help) => Err(help)
+        }
+    }
+
+    #[test]
+    fn parses_flags_from_the_table() {
+        let parsed = run(&["-t", "a.txt", "--vocab-size=300", "b/", "--special", "<|a|>", "--special=<|b|>", "-q", "--", "-c"], Mode::Cli).unwrap();
+        assert_eq!(parsed.command.name, "train");
+        assert_eq!(parsed.positionals, ["a.txt", "b/", "-c"]);
+        assert_eq!(parsed.value("vocab-size"), Some("300"));
+        assert_eq!(parsed.value("min-frequency"), Some("1"));
+        assert_eq!(parsed.value("memory-limit"), Some("1024"));
+        assert_eq!(parsed.values("special"), ["<|a|>", "<|b|>"]);
+        assert!(parsed.switch("quiet"));
+
+        assert!(run(&["train", "a.txt", "--bogus"], Mode::Cli).unwrap_err().starts_with("Unknown flag --bogus"));
+        assert!(run(&["encode", "a.txt"], Mode::Cli).unwrap_err().starts_with("Missing --model"));
+        assert!(run(&["train", "a.txt", "-q=1"], Mode::Cli).is_err());
+        assert!(run(&["continue", "a.txt", "--type", "regex"], Mode::Repl).is_err());
+        assert!(run(&["continue", "a.txt"], Mode::Cli).is_err());
+        assert!(matches!(parse(&["train", "--help"], Mode::Cli), Ok(Invocation::Help(help)) if help.contains("--vocab-size <n>")));
+    }
+}
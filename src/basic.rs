@@ -0,0 +1,586 @@
+use core::fmt;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::corpus::{read_sources, ChunkCounter, Chunks, Fingerprint, Source};
+use crate::binary::{self, BinaryModel};
+use crate::error::TokenizerError;
+use crate::minbpe;
+use crate::model::{self, Diagnostics};
+use crate::special::{split_special, AllowedSpecial, Segment};
+use crate::stream::Cut;
+use crate::tiktoken;
+use crate::tokenizer::Tokenizer;
+use crate::train::{learn_sequence, Sequence, TrainOptions, TrainParams};
+use crate::validate;
+
+// the problems validate found as one error
+pub(crate) fn invalid_if_any(problems:Vec<String>) -> Result<(), TokenizerError> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(TokenizerError::Invalid(format!("Model has {} problem{}:\n\t{}", problems.len(), if problems.len() == 1 { "" } else { "s" }, problems.join("\n\t"))))
+}
+
+// replaces every occurrence of pair in ids with idx, left to right
+pub fn merge(ids: &[u32], pair:&(u32, u32), idx:&u32) -> Vec<u32>{
+    let mut newids: Vec<u32> = Vec::with_capacity(ids.len());
+    let mut i = 0;
+    while i < ids.len() {
+        if ids[i] == pair.0 && i < ids.len() - 1 && ids[i+1] == pair.1{
+            newids.push(*idx);
+            i += 2;
+        } else {
+          newids.push(ids[i]);
+          i +=1;
+        }
+    }
+    newids
+}
+
+// every document of the sources as one chunk, and the fingerprint of the sources. a document is
+// read whole since it's a single chunk, with options.dedup they're counted like regex chunks so
+// the table spills to disk past options.memory_limit. without it they're all kept, so training
+// fails once a document, or all of them together without dedup, take more than the limit
+pub(crate) fn document_chunks(sources:&[Source], verbose:bool, options:&TrainOptions) -> Result<(Chunks, Fingerprint), TokenizerError> {
+    let mut counter = ChunkCounter::new(options.memory_limit, options.spill_dir.as_deref());
+    let mut chunks: Vec<(Vec<u8>, u64)> = Vec::new();
+    let mut kept = 0;
+    let fingerprint = read_sources(sources, verbose, |path, file| {
+        let name = path.to_str().unwrap_or("?");
+        let mut document = Vec::new();
+        let limit = options.memory_limit.saturating_sub(kept);
+        file.take(limit as u64 + 1).read_to_end(&mut document).map_err(TokenizerError::io(format!("reading {}", name)))?;
+        if document.len() > limit && (options.dedup || kept == 0) {
+            return Err(TokenizerError::Invalid(format!("{} is larger than the memory limit of {} bytes, documents are read whole", name, options.memory_limit)));
+        }
+        if document.len() > limit {
+            return Err(TokenizerError::Invalid(format!("The documents up to {} take more than the memory limit of {} bytes, they're all kept in memory without dedup", name, options.memory_limit)));
+        }
+        let bytes = document.len() as u64;
+        if options.dedup {
+            counter.add(&document)?;
+        } else {
+            kept += document.len();
+            chunks.push((document, 1));
+        }
+        Ok(bytes)
+    })?;
+    let chunks = if options.dedup { Chunks::Counted(counter) } else { Chunks::Bytes(chunks) };
+    Ok((chunks, fingerprint))
+}
+
+#[derive(Debug)]
+pub struct BasicTokenizer {
+    pub(crate) trained: bool,
+    pub(crate) vocab_size: u32,
+    pub(crate) num_merges: u32,
+
+    pub(crate) merges: HashMap<(u32,u32),u32>,
+    // token id -> the utf-8 bytes it expands to
+    pub(crate) vocab: HashMap<u32, Vec<u8>>,
+    // ids above the merge range, not part of vocab
+    pub(crate) special_tokens: HashMap<String, u32>,
+    // what the last training run was asked for, none for models saved without them
+    pub(crate) params: Option<TrainParams>,
+    // the corpus of the last training run
+    pub(crate) corpus: Option<Fingerprint>,
+    // free-form key value pairs saved with the model, like where it came from
+    pub(crate) metadata: BTreeMap<String, String>
+}
+
+impl BasicTokenizer {
+    // for text already in memory, train_from_sources reads files
+    pub fn train(text:&str, vocab_size:u32, verbose:Option<bool>) -> Result<Self, TokenizerError> {
+        Self::train_with_options(text, vocab_size, verbose, &TrainOptions::default())
+    }
+
+    // only options.threads applies, the text is a single chunk so there is nothing to dedup
+    pub fn train_with_options(text:&str, vocab_size:u32, verbose:Option<bool>, options:&TrainOptions) -> Result<Self, TokenizerError> {
+        // train on the raw utf-8 bytes, ids 0..256 are the bytes themselves.
+        // the whole text is a single chunk, merges can cross anything
+        Self::train_chunks(Chunks::Bytes(vec![(text.as_bytes().to_vec(), 1)]), vocab_size, verbose, options, Fingerprint::of(text.as_bytes()))
+    }
+
+    // every document is a chunk of its own so merges can cross anything but the end of a document,
+    // identical documents are counted once with options.dedup
+    pub fn train_from_sources(sources:&[Source], vocab_size:u32, verbose:Option<bool>, options:&TrainOptions) -> Result<Self, TokenizerError> {
+        let (chunks, corpus) = document_chunks(sources, verbose.unwrap_or(false), options)?;
+        Self::train_chunks(chunks, vocab_size, verbose, options, corpus)
+    }
+
+    pub(crate) fn train_chunks(chunks:Chunks, vocab_size:u32, verbose:Option<bool>, options:&TrainOptions, corpus:Fingerprint) -> Result<Self, TokenizerError> {
+        Self::check_vocab_size(vocab_size)?;
+
+        let mut tokenizer = Self::untrained();
+        tokenizer.learn(chunks, vocab_size, verbose, options, corpus)?;
+        Ok(tokenizer)
+    }
+
+    // the 256 byte tokens are always there
+    pub(crate) fn check_vocab_size(vocab_size:u32) -> Result<(), TokenizerError> {
+        if vocab_size < 256 {
+            return Err(TokenizerError::Invalid(format!("vocab_size has to be at least 256, got {}", vocab_size)));
+        }
+        Ok(())
+    }
+
+    // just the 256 byte tokens
+    pub(crate) fn untrained() -> Self {
+        BasicTokenizer{
+            trained: false,
+            vocab_size: 256,
+            num_merges: 0,
+            merges: HashMap::new(),
+            vocab: (0..=255u8).map(|b| (b as u32, vec![b])).collect(),
+            special_tokens: HashMap::new(),
+            params: None,
+            corpus: None,
+            metadata: BTreeMap::new()
+        }
+    }
+
+    // keeps every existing id and learns new merges on top of them until vocab_size, e.g. to adapt
+    // a general tokenizer to a domain without changing the ids a model already uses.
+    // the model is left as it was on errors
+    pub fn continue_from_sources(&mut self, sources:&[Source], vocab_size:u32, verbose:Option<bool>, options:&TrainOptions) -> Result<(), TokenizerError> {
+        self.check_continue(vocab_size)?;
+        let (chunks, corpus) = document_chunks(sources, verbose.unwrap_or(false), options)?;
+        self.learn(chunks, vocab_size, verbose, options, corpus)
+    }
+
+    // the new merges take the ids right after the existing ones, special tokens can't be in the way
+    pub fn check_continue(&self, vocab_size:u32) -> Result<(), TokenizerError> {
+        let first_idx = 256 + self.merges.len() as u32;
+        if vocab_size <= first_idx {
+            return Err(TokenizerError::Invalid(format!("Model already has {} tokens, vocab_size has to be larger to continue training", first_idx)));
+        }
+        if let Some((special, idx)) = self.special_tokens.iter().filter(|(_, idx)| (first_idx..vocab_size).contains(*idx)).min_by_key(|(_, idx)| **idx) {
+            return Err(TokenizerError::Invalid(format!("Special token {} has id {} which the new merges would take, vocab_size can be at most {}", special, idx, idx)));
+        }
+        Ok(())
+    }
+
+    // chunks are encoded with the existing merges as they're read and training continues from
+    // there, corpus is what they came from. the model is left as it was on errors
+    pub(crate) fn learn(&mut self, chunks:Chunks, vocab_size:u32, verbose:Option<bool>, options:&TrainOptions, corpus:Fingerprint) -> Result<(), TokenizerError> {
+        let verbos = verbose.unwrap_or(false);
+
+        let first_idx = 256 + self.merges.len() as u32;
+        let num_merges = vocab_size - first_idx;
+
+        let seq = Sequence::read(chunks, |ids| self.encode_ids(ids))?;
+        let (merges, vocab) = (&mut self.merges, &mut self.vocab);
+        let learned = learn_sequence(seq, first_idx, num_merges, options, |m| {
+            merges.insert(m.pair, m.idx);
+            vocab.insert(m.idx, [vocab[&m.pair.0].clone(), vocab[&m.pair.1].clone()].concat());
+
+            if verbos {
+                eprintln!("merge {}/{}: {:?} -> {} ({:?} had {} occurrences)", m.idx-first_idx+1, num_merges, m.pair, m.idx, vocab[&m.idx], m.count)
+            }
+        });
+        if verbos && (learned.len() as u32) < num_merges {
+            eprintln!("Stopped after {} merges, no pairs left that occur at least {} times", learned.len(), options.min_frequency);
+        }
+        self.trained = true;
+        self.params = Some(TrainParams{ vocab_size, min_frequency: options.min_frequency });
+        self.corpus = Some(corpus);
+        self.num_merges = self.merges.len() as u32;
+        self.vocab_size = 256 + self.num_merges;
+        Ok(())
+    }
+
+    // special tokens must have ids above the merge range that aren't taken yet, the whole batch is
+    // checked before any of it is registered and registered tokens keep their id
+    pub fn register_special_tokens(&mut self, special_tokens:HashMap<String, u32>) -> Result<(), TokenizerError> {
+        for (special, idx) in &special_tokens {
+            if special.is_empty() {
+                return Err(TokenizerError::Invalid("Special tokens can't be empty".to_string()));
+            }
+            if *idx < 256 + self.num_merges || self.vocab.contains_key(idx) {
+                return Err(TokenizerError::Invalid(format!("Special token {} has id {} which is in the merge range", special, idx)));
+            }
+            if let Some(old) = self.special_tokens.get(special).filter(|old| *old != idx) {
+                return Err(TokenizerError::Invalid(format!("Special token {} is already registered with id {}, not {}", special, old, idx)));
+            }
+            let taken = self.special_tokens.iter().chain(&special_tokens).find(|(other, other_idx)| *other_idx == idx && *other != special);
+            if let Some((other, _)) = taken {
+                return Err(TokenizerError::Invalid(format!("Special token {} has id {} which is already taken by {}", special, idx, other)));
+            }
+        }
+        self.special_tokens.extend(special_tokens);
+        Ok(())
+    }
+
+    // registers the tokens that aren't registered yet with the next free ids in order
+    pub fn register_next_special_tokens(&mut self, tokens:Vec<String>) -> Result<(), TokenizerError> {
+        let first = self.next_special_id();
+        let mut new: Vec<String> = Vec::new();
+        for token in tokens {
+            if !self.special_tokens.contains_key(&token) && !new.contains(&token) {
+                new.push(token);
+            }
+        }
+        self.register_special_tokens(new.into_iter().enumerate().map(|(i, token)| (token, first + i as u32)).collect())
+    }
+
+    pub fn next_special_id(&self) -> u32 {
+        self.special_tokens.values().map(|idx| idx + 1).max().unwrap_or(0).max(256 + self.num_merges)
+    }
+
+    pub fn decode(&self, ids: Vec<u32>) -> Result<String, TokenizerError> {
+        let text_bytes = self.decode_bytes(&ids)?;
+        // a slice of ids can end in the middle of a multibyte char, replace instead of failing like minbpe does
+        Ok(String::from_utf8_lossy(&text_bytes).into_owned())
+    }
+
+    pub fn decode_bytes(&self, ids:&[u32]) -> Result<Vec<u8>, TokenizerError> {
+        let mut text_bytes: Vec<u8> = Vec::new();
+        for id in ids {
+            match self.vocab.get(id) {
+                Some(bytes) => text_bytes.extend_from_slice(bytes),
+                None => {
+                    let (special, _) = self.special_tokens.iter().find(|(_, idx)| *idx == id).ok_or(TokenizerError::UnknownId(*id))?;
+                    text_bytes.extend_from_slice(special.as_bytes());
+                }
+            }
+        }
+        Ok(text_bytes)
+    }
+
+    // the bytes of a token or special token
+    pub fn id_to_bytes(&self, id:u32) -> Option<&[u8]> {
+        match self.vocab.get(&id) {
+            Some(bytes) => Some(bytes),
+            None => self.special_tokens.iter().find(|(_, idx)| **idx == id).map(|(special, _)| special.as_bytes())
+        }
+    }
+
+    // a scan over the whole vocab, the lowest id if several tokens have the same bytes
+    pub fn bytes_to_id(&self, bytes:&[u8]) -> Option<u32> {
+        let special = std::str::from_utf8(bytes).ok().and_then(|text| self.special_tokens.get(text));
+        self.vocab.iter().filter(|(_, token)| token.as_slice() == bytes).map(|(id, _)| *id).min().or(special.copied())
+    }
+
+    // byte pairs that are next to each other in some token, text cut between any other two
+    // bytes encodes to the same ids as the two parts encoded on their own
+    pub fn joined_pairs(&self) -> Vec<bool> {
+        let mut joined = vec![false; 256 * 256];
+        for bytes in self.vocab.values() {
+            for pair in bytes.windows(2) {
+                joined[pair[0] as usize * 256 + pair[1] as usize] = true;
+            }
+        }
+        joined
+    }
+
+    pub fn encode(&self, text:&str) -> Vec<u32> {
+        let ids:Vec<u32> = text.bytes().map(u32::from).collect();
+        self.encode_ids(ids)
+    }
+
+    pub fn encode_with_special(&self, text:&str, allowed:&AllowedSpecial) -> Result<Vec<u32>, TokenizerError> {
+        let mut ids = Vec::with_capacity(text.len());
+        for segment in split_special(text, &self.special_tokens, allowed)? {
+            match segment {
+                Segment::Text(part) => ids.extend(self.encode(part)),
+                Segment::Special(idx) => ids.push(idx)
+            }
+        }
+        Ok(ids)
+    }
+
+    // merges are applied in the order they were learned, the merge idx doubles as its rank.
+    // same as minbpe's min(stats, key=lambda p: merges.get(p, inf)) so the input statistics don't matter
+    pub fn encode_ids(&self, mut ids: Vec<u32>) -> Vec<u32> {
+        while ids.len() >= 2 {
+            let lowest = ids.iter().zip(ids.iter().skip(1))
+                .filter_map(|(a, b)| self.merges.get(&(*a, *b)).map(|idx| (*idx, (*a, *b))))
+                .min();
+            match lowest {
+                Some((idx, pair)) => {
+                    ids = merge(&ids, &pair, &idx);
+                },
+                // nothing left to merge
+                None => break
+            }
+        }
+        ids
+    }
+
+    pub fn vocab_size(&self) -> u32 {
+        self.vocab_size
+    }
+
+    // pair -> the id it merges into, which is also its rank
+    pub fn merges(&self) -> &HashMap<(u32, u32), u32> {
+        &self.merges
+    }
+
+    pub fn vocab(&self) -> &HashMap<u32, Vec<u8>> {
+        &self.vocab
+    }
+
+    pub fn special_tokens(&self) -> &HashMap<String, u32> {
+        &self.special_tokens
+    }
+
+    pub fn params(&self) -> Option<&TrainParams> {
+        self.params.as_ref()
+    }
+
+    pub fn corpus(&self) -> Option<Fingerprint> {
+        self.corpus
+    }
+
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    // saved with the model on a line of its own, so keys can't be empty or have whitespace in them
+    // and values can't have line breaks
+    pub fn set_metadata(&mut self, key:&str, value:&str) -> Result<(), TokenizerError> {
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(TokenizerError::Invalid(format!("Metadata key {:?} is empty or has whitespace in it", key)));
+        }
+        if value.contains(['\n', '\r']) {
+            return Err(TokenizerError::Invalid(format!("Metadata value {:?} has a line break in it", value)));
+        }
+        self.metadata.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    // the format by extension, see Format::from_path. .model is our text format, minbpe's is only
+    // written with save_as and Format::Minbpe
+    pub fn save(&self, path:&Path) -> Result<(), TokenizerError> {
+        Tokenizer::save(self, path)
+    }
+
+    // see model::write_model for the format
+    pub fn save_str(&self) -> String {
+        model::write_model(self, "")
+    }
+
+    // tokenizer::load also takes regex models
+    pub fn load(path:&Path) -> Result<Self, TokenizerError> {
+        let (tokenizer, _pattern) = Self::read_model(path)?;
+        tokenizer.validate()?;
+        Ok(tokenizer)
+    }
+
+    // everything inconsistent between the merges, vocab and special tokens, like a hand edited
+    // model can have. empty if there's nothing
+    pub fn problems(&self) -> Vec<String> {
+        validate::problems(self, true)
+    }
+
+    pub fn validate(&self) -> Result<(), TokenizerError> {
+        invalid_if_any(self.problems())
+    }
+
+    // a binary model or one of the text formats parse_any takes, with its split pattern
+    pub(crate) fn read_model(path:&Path) -> Result<(Self, String), TokenizerError> {
+        let bytes = fs::read(path).map_err(TokenizerError::io(format!("reading model from {}", path.to_str().unwrap_or("?"))))?;
+        if binary::is_binary(&bytes) {
+            return BinaryModel::parse(&bytes)?.to_tokenizer();
+        }
+        let text = std::str::from_utf8(&bytes).map_err(|e| TokenizerError::InvalidUtf8(format!("Model at {} isn't a binary model or valid utf-8: {}", path.to_str().unwrap_or("?"), e)))?;
+        Self::parse_any(text, &mut Diagnostics::default())
+    }
+
+    // ours or minbpe's format, minbpe's files start with its version line. only ours can be read
+    // leniently, minbpe's are always strict
+    pub(crate) fn parse_any(text:&str, diagnostics:&mut Diagnostics) -> Result<(Self, String), TokenizerError> {
+        if text.lines().next().is_some_and(|line| line.trim() == minbpe::VERSION) {
+            return minbpe::parse_model(text);
+        }
+        model::parse_model_with(text, diagnostics)
+    }
+}
+
+impl fmt::Display for BasicTokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = Vec::new();
+        writeln!(out,"BasicTokenizer:\n\t   trained: {}\n\tvocab_size: {}\n\tnum_merges: {}\n\tmerges:",self.trained,self.vocab_size,self.num_merges).unwrap();
+
+        for elem in self.merges.borrow(){
+            writeln!(out, "\t\t({:4},{:4}) -> {}", elem.0.0, elem.0.1, elem.1).unwrap();
+        }
+
+        writeln!(out,"\tvocab:", ).unwrap();
+
+        for voc in self.vocab.borrow(){
+            writeln!(out, "\t\t{:<4} : {:?}", voc.0, String::from_utf8_lossy(voc.1)).unwrap()
+        }
+
+        if let Some(params) = &self.params {
+            writeln!(out,"\ttrained with: {}", params.to_line()).unwrap();
+        }
+
+        if let Some(corpus) = &self.corpus {
+            writeln!(out,"\tcorpus: {}", corpus).unwrap();
+        }
+
+        for (key, value) in &self.metadata {
+            writeln!(out,"\tmeta {}: {}", key, value).unwrap();
+        }
+
+        writeln!(out,"\tspecial tokens:", ).unwrap();
+
+        for special in self.special_tokens.borrow(){
+            writeln!(out, "\t\t{:<4} : {:?}", special.1, special.0).unwrap()
+        }
+
+        write!(f, "{}", String::from_utf8(out).unwrap())
+    }
+}
+
+impl Tokenizer for BasicTokenizer {
+    fn encode_with_special(&self, text:&str, allowed:&AllowedSpecial) -> Result<Vec<u32>, TokenizerError> {
+        self.encode_with_special(text, allowed)
+    }
+
+    fn decode_bytes(&self, ids:&[u32]) -> Result<Vec<u8>, TokenizerError> {
+        self.decode_bytes(ids)
+    }
+
+    fn vocab_size(&self) -> u32 {
+        self.vocab_size
+    }
+
+    fn id_to_bytes(&self, id:u32) -> Option<&[u8]> {
+        self.id_to_bytes(id)
+    }
+
+    fn bytes_to_id(&self, bytes:&[u8]) -> Option<u32> {
+        self.bytes_to_id(bytes)
+    }
+
+    fn special_tokens(&self) -> &HashMap<String, u32> {
+        &self.special_tokens
+    }
+
+    fn add_special_tokens(&mut self, tokens:Vec<String>) -> Result<(), TokenizerError> {
+        self.register_next_special_tokens(tokens)
+    }
+
+    fn metadata(&self) -> &BTreeMap<String, String> {
+        self.metadata()
+    }
+
+    fn set_metadata(&mut self, key:&str, value:&str) -> Result<(), TokenizerError> {
+        self.set_metadata(key, value)
+    }
+
+    fn problems(&self) -> Vec<String> {
+        self.problems()
+    }
+
+    // merges cross anything, so only where no token joins the two bytes
+    fn cut(&self) -> Cut {
+        Cut::Unjoined(self.joined_pairs())
+    }
+
+    fn continue_from_sources(&mut self, sources:&[Source], vocab_size:u32, verbose:Option<bool>, options:&TrainOptions) -> Result<(), TokenizerError> {
+        self.continue_from_sources(sources, vocab_size, verbose, options)
+    }
+
+    fn save_str(&self) -> Result<String, TokenizerError> {
+        Ok(self.save_str())
+    }
+
+    fn to_minbpe(&self) -> Result<(String, String), TokenizerError> {
+        Ok((minbpe::write_model(self, "")?, minbpe::write_vocab(self)))
+    }
+
+    fn to_binary(&self) -> Result<Vec<u8>, TokenizerError> {
+        binary::write_model(self, "")
+    }
+
+    fn to_tiktoken(&self) -> Result<String, TokenizerError> {
+        tiktoken::export_model(self, "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::{RegexTokenizer, GPT4_SPLIT_PATTERN};
+
+    #[test]
+    fn multibyte_text_round_trips() {
+        let text = "Çok güzel, ığdır şöyle! 東京は日本の首都です。 🦀🚀 aile 👩‍👩‍👧\n".repeat(5);
+        let basic = BasicTokenizer::train(&text, 320, None).unwrap();
+        let regex = RegexTokenizer::train(&text, 320, None, GPT4_SPLIT_PATTERN).unwrap();
+        // some tokens are only part of a char
+        assert!(basic.vocab.values().any(|bytes| std::str::from_utf8(bytes).is_err()));
+        for sample in [text.as_str(), "İĞÜŞÖÇ ığüşöç", "日本語のテキスト", "👩‍👩‍👧🦀", "ş東🚀x"] {
+            assert_eq!(basic.decode(basic.encode(sample)).unwrap(), sample);
+            assert_eq!(regex.decode(regex.encode(sample).unwrap()).unwrap(), sample);
+        }
+    }
+
+    #[test]
+    fn encode_merges_by_rank_not_by_count() {
+        let mut tokenizer = BasicTokenizer::train("", 256, None).unwrap();
+        for (pair, idx) in [((b'b' as u32, b'c' as u32), 256), ((b'a' as u32, b'b' as u32), 257)] {
+            tokenizer.merges.insert(pair, idx);
+            tokenizer.vocab.insert(idx, [tokenizer.vocab[&pair.0].clone(), tokenizer.vocab[&pair.1].clone()].concat());
+        }
+        tokenizer.num_merges = 2;
+        tokenizer.vocab_size = 258;
+        // "ab" occurs three times and "bc" once, but "bc" has the lower rank so it's merged first
+        // and takes the b that "ab" would have used
+        let ids = tokenizer.encode("abcabab");
+        assert_eq!(ids, vec![b'a' as u32, 256, 257, 257]);
+        assert_eq!(tokenizer.decode(ids).unwrap(), "abcabab");
+    }
+
+    #[test]
+    fn documents_past_the_memory_limit_fail() {
+        let dir = std::env::temp_dir().join(format!("rust_tokenizer-documents-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files: Vec<_> = ["a.txt", "b.txt"].iter().map(|name| dir.join(name)).collect();
+        for file in &files {
+            std::fs::write(file, "x".repeat(100)).unwrap();
+        }
+        let sources = [Source{ name: "docs".to_string(), files }];
+        let read = |memory_limit, dedup| document_chunks(&sources, false, &TrainOptions{ memory_limit, dedup, ..TrainOptions::default() }).is_ok();
+        // with dedup only each document has to fit, without it all of them together
+        assert!(read(150, true));
+        assert!(!read(150, false));
+        assert!(read(200, false));
+        assert!(!read(50, true));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn registered_specials_keep_their_id() {
+        let mut tokenizer = BasicTokenizer::untrained();
+        tokenizer.register_special_tokens(HashMap::from([("<|a|>".to_string(), 300)])).unwrap();
+        assert!(tokenizer.register_special_tokens(HashMap::from([("<|a|>".to_string(), 301)])).is_err());
+        tokenizer.register_special_tokens(HashMap::from([("<|a|>".to_string(), 300)])).unwrap();
+        tokenizer.register_next_special_tokens(vec!["<|a|>".to_string(), "<|b|>".to_string(), "<|b|>".to_string()]).unwrap();
+        assert_eq!(tokenizer.special_tokens, HashMap::from([("<|a|>".to_string(), 300), ("<|b|>".to_string(), 301)]));
+    }
+
+    #[test]
+    fn bad_special_batch_registers_nothing() {
+        let mut tokenizer = BasicTokenizer::untrained();
+        tokenizer.register_special_tokens(HashMap::from([("<|a|>".to_string(), 300)])).unwrap();
+        for batch in [
+            vec![("<|b|>", 301), ("", 302)],
+            vec![("<|b|>", 301), ("<|c|>", 100)],
+            vec![("<|b|>", 301), ("<|c|>", 300)],
+            vec![("<|b|>", 301), ("<|c|>", 301)],
+            vec![("<|b|>", 301), ("<|a|>", 302)],
+        ] {
+            let batch = batch.into_iter().map(|(token, idx)| (token.to_string(), idx)).collect();
+            assert!(tokenizer.register_special_tokens(batch).is_err());
+            assert_eq!(tokenizer.special_tokens, HashMap::from([("<|a|>".to_string(), 300)]));
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use crate::basic::BasicTokenizer;
+use crate::corpus::Fingerprint;
+use crate::error::TokenizerError;
+use crate::train::TrainParams;
+
+// first bytes of a binary model
+pub const MAGIC: &[u8; 8] = b"RTOKBIN\0";
+pub const VERSION: u32 = 1;
+pub const HEADER_SIZE: usize = 72;
+
+const KIND_BASIC: u32 = 0;
+const KIND_REGEX: u32 = 1;
+const HAS_PARAMS: u32 = 1;
+const HAS_CORPUS: u32 = 2;
+
+// the fixed header, every number little-endian:
+//  0 magic            8 bytes
+//  8 version          u32
+// 12 kind             u32, 0 basic 1 regex
+// 16 flags            u32, 1 params are set, 2 corpus is set
+// 20 num_merges       u32
+// 24 num_specials     u32
+// 28 num_meta         u32
+// 32 pattern_len      u32
+// 36 params vocab_size    u32
+// 40 params min_frequency u64
+// 48 corpus           u64
+// 56 body_len         u64
+// 64 checksum         u64, fnv-1a over the body
+// the body follows at 72:
+// merges              (a u32, b u32) each, merge i gets id 256 + i
+// pattern             pattern_len bytes of utf-8
+// specials            (id u32, len u32, bytes) each, in id order
+// metadata            (key len u32, key, value len u32, value) each, in key order
+// the merge table comes first and is 8 byte aligned, so a mapped file can be read in place
+#[derive(Debug, Clone)]
+struct Header {
+    kind: u32,
+    flags: u32,
+    num_merges: u32,
+    num_specials: u32,
+    num_meta: u32,
+    pattern_len: u32,
+    params: TrainParams,
+    corpus: u64,
+    body_len: u64,
+    checksum: u64
+}
+
+pub fn is_binary(bytes:&[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+// merge ids have to count up from 256, the vocab is built from the merges when it's loaded
+pub fn write_model(tokenizer:&BasicTokenizer, pattern:&str) -> Result<Vec<u8>, TokenizerError> {
+    let mut merges: Vec<(&(u32, u32), &u32)> = tokenizer.merges.iter().collect();
+    merges.sort_by_key(|(_, idx)| **idx);
+    if let Some((_, ((a, b), idx))) = merges.iter().enumerate().find(|(i, (_, idx))| **idx != 256 + *i as u32) {
+        return Err(TokenizerError::Invalid(format!("Merge ({},{}) has id {}, the binary format needs merge ids to count up from 256", a, b, idx)));
+    }
+    let mut specials: Vec<(&u32, &String)> = tokenizer.special_tokens.iter().map(|(special, idx)| (idx, special)).collect();
+    specials.sort();
+
+    let mut body = Vec::new();
+    for ((a, b), _) in &merges {
+        body.extend(a.to_le_bytes());
+        body.extend(b.to_le_bytes());
+    }
+    body.extend(pattern.as_bytes());
+    for (idx, special) in &specials {
+        body.extend(idx.to_le_bytes());
+        push_bytes(&mut body, special.as_bytes());
+    }
+    for (key, value) in &tokenizer.metadata {
+        push_bytes(&mut body, key.as_bytes());
+        push_bytes(&mut body, value.as_bytes());
+    }
+
+    let params = tokenizer.params.clone().unwrap_or(TrainParams{ vocab_size: 0, min_frequency: 0 });
+    let flags = if tokenizer.params.is_some() { HAS_PARAMS } else { 0 } | if tokenizer.corpus.is_some() { HAS_CORPUS } else { 0 };
+    let mut model = Vec::with_capacity(HEADER_SIZE + body.len());
+    model.extend(MAGIC);
+    for n in [VERSION, if pattern.is_empty() { KIND_BASIC } else { KIND_REGEX }, flags, merges.len() as u32, specials.len() as u32, tokenizer.metadata.len() as u32, pattern.len() as u32, params.vocab_size] {
+        model.extend(n.to_le_bytes());
+    }
+    for n in [params.min_frequency, tokenizer.corpus.map_or(0, |corpus| corpus.0), body.len() as u64, Fingerprint::of(&body).0] {
+        model.extend(n.to_le_bytes());
+    }
+    model.extend(body);
+    Ok(model)
+}
+
+fn push_bytes(body:&mut Vec<u8>, bytes:&[u8]) {
+    body.extend((bytes.len() as u32).to_le_bytes());
+    body.extend(bytes);
+}
+
+// a checked binary model borrowing the bytes it was read from, like a mapped file. nothing is
+// copied until it's turned into a tokenizer
+#[derive(Debug)]
+pub struct BinaryModel<'a> {
+    header: Header,
+    merges: &'a [u8],
+    pattern: &'a str,
+    special_tokens: Vec<(&'a str, u32)>,
+    metadata: Vec<(&'a str, &'a str)>
+}
+
+impl<'a> BinaryModel<'a> {
+    // checks the magic, version, section sizes and checksum
+    pub fn parse(bytes:&'a [u8]) -> Result<Self, TokenizerError> {
+        if !is_binary(bytes) {
+            return Err(invalid(0, "doesn't start with the binary model magic"));
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(invalid(bytes.len(), format!("the header is {} bytes, the file ends before it", HEADER_SIZE)));
+        }
+        let u32_at = |at:usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap_or_default());
+        let u64_at = |at:usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap_or_default());
+        let version = u32_at(8);
+        if version != VERSION {
+            return Err(invalid(8, format!("version {} isn't one this build reads, expected {}", version, VERSION)));
+        }
+        let header = Header{
+            kind: u32_at(12), flags: u32_at(16), num_merges: u32_at(20), num_specials: u32_at(24), num_meta: u32_at(28), pattern_len: u32_at(32),
+            params: TrainParams{ vocab_size: u32_at(36), min_frequency: u64_at(40) },
+            corpus: u64_at(48), body_len: u64_at(56), checksum: u64_at(64)
+        };
+        if header.kind != KIND_BASIC && header.kind != KIND_REGEX {
+            return Err(invalid(12, format!("unknown tokenizer kind {}", header.kind)));
+        }
+        let body = &bytes[HEADER_SIZE..];
+        if body.len() as u64 != header.body_len {
+            return Err(invalid(bytes.len(), format!("the body is {} bytes, the header says {}, the file is cut off or has trailing bytes", body.len(), header.body_len)));
+        }
+        if Fingerprint::of(body).0 != header.checksum {
+            return Err(invalid(64, "the checksum doesn't match the body, the file is corrupted"));
+        }
+
+        let mut reader = Sections{ body, at: 0 };
+        let merges = reader.take(header.num_merges as usize * 8, "merges")?;
+        let pattern = reader.text(header.pattern_len as usize, "pattern")?;
+        if (header.kind == KIND_REGEX) == pattern.is_empty() {
+            return Err(invalid(12, "the kind doesn't match the pattern, regex tokenizers have one and basic ones don't"));
+        }
+        let mut special_tokens = Vec::new();
+        for _ in 0..header.num_specials {
+            let idx = reader.number("special token id")?;
+            let len = reader.number("special token length")?;
+            special_tokens.push((reader.text(len as usize, "special token")?, idx));
+        }
+        let mut metadata = Vec::new();
+        for _ in 0..header.num_meta {
+            let len = reader.number("metadata key length")?;
+            let key = reader.text(len as usize, "metadata key")?;
+            let len = reader.number("metadata value length")?;
+            metadata.push((key, reader.text(len as usize, "metadata value")?));
+        }
+        if reader.at != body.len() {
+            return Err(invalid(HEADER_SIZE + reader.at, "trailing bytes after the metadata"));
+        }
+        Ok(BinaryModel{ header, merges, pattern, special_tokens, metadata })
+    }
+
+    // pair -> id in id order, read straight from the bytes
+    pub fn merges(&self) -> impl Iterator<Item = ((u32, u32), u32)> + 'a {
+        self.merges.chunks_exact(8).enumerate().map(|(i, pair)| {
+            let id = |at:usize| u32::from_le_bytes([pair[at], pair[at + 1], pair[at + 2], pair[at + 3]]);
+            ((id(0), id(4)), 256 + i as u32)
+        })
+    }
+
+    // empty for basic tokenizers
+    pub fn pattern(&self) -> &'a str {
+        self.pattern
+    }
+
+    pub fn special_tokens(&self) -> &[(&'a str, u32)] {
+        &self.special_tokens
+    }
+
+    pub fn metadata(&self) -> &[(&'a str, &'a str)] {
+        &self.metadata
+    }
+
+    // returns the tokenizer and its split pattern, which is empty for basic models
+    pub fn to_tokenizer(&self) -> Result<(BasicTokenizer, String), TokenizerError> {
+        let mut tokenizer = BasicTokenizer::untrained();
+        for (i, ((a, b), idx)) in self.merges().enumerate() {
+            if a >= idx || b >= idx {
+                return Err(invalid(HEADER_SIZE + i * 8, format!("merge ({},{}) uses an id that isn't below its own id {}", a, b, idx)));
+            }
+            let bytes = [tokenizer.vocab[&a].as_slice(), tokenizer.vocab[&b].as_slice()].concat();
+            tokenizer.merges.insert((a, b), idx);
+            tokenizer.vocab.insert(idx, bytes);
+        }
+        tokenizer.trained = true;
+        tokenizer.num_merges = self.header.num_merges;
+        tokenizer.vocab_size = 256 + self.header.num_merges;
+        tokenizer.params = (self.header.flags & HAS_PARAMS != 0).then(|| self.header.params.clone());
+        tokenizer.corpus = (self.header.flags & HAS_CORPUS != 0).then_some(Fingerprint(self.header.corpus));
+        tokenizer.metadata = self.metadata.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        tokenizer.register_special_tokens(self.special_tokens.iter().map(|(special, idx)| (special.to_string(), *idx)).collect::<HashMap<_, _>>())?;
+        Ok((tokenizer, self.pattern.to_string()))
+    }
+}
+
+// at is the byte offset in the file
+fn invalid(at:usize, message:impl std::fmt::Display) -> TokenizerError {
+    TokenizerError::Invalid(format!("Binary model at byte {}: {}", at, message))
+}
+
+// reads the body section by section, anything running past its end is an error
+struct Sections<'a> {
+    body: &'a [u8],
+    at: usize
+}
+
+impl<'a> Sections<'a> {
+    fn take(&mut self, len:usize, what:&str) -> Result<&'a [u8], TokenizerError> {
+        let bytes = self.body.get(self.at..self.at.saturating_add(len))
+            .ok_or_else(|| invalid(HEADER_SIZE + self.at, format!("the {} runs past the end of the body", what)))?;
+        self.at += len;
+        Ok(bytes)
+    }
+
+    fn number(&mut self, what:&str) -> Result<u32, TokenizerError> {
+        let bytes = self.take(4, what)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn text(&mut self, len:usize, what:&str) -> Result<&'a str, TokenizerError> {
+        let at = self.at;
+        std::str::from_utf8(self.take(len, what)?).map_err(|e| invalid(HEADER_SIZE + at, format!("the {} isn't valid utf-8: {}", what, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model;
+    use crate::testing;
+
+    #[test]
+    fn binary_models_read_back_and_are_checked() {
+        let mut tokenizer = testing::basic(280);
+        tokenizer.register_special_tokens(HashMap::from([("<|end|>".to_string(), 280)])).unwrap();
+        tokenizer.set_metadata("source", "a test").unwrap();
+        let bytes = write_model(&tokenizer, "\\w+|\\s+").unwrap();
+
+        let binary = BinaryModel::parse(&bytes).unwrap();
+        assert_eq!(binary.pattern(), "\\w+|\\s+");
+        assert_eq!(binary.special_tokens(), [("<|end|>", 280)]);
+        assert_eq!(binary.merges().count(), 24);
+        let (loaded, pattern) = binary.to_tokenizer().unwrap();
+        assert_eq!(model::write_model(&loaded, &pattern), model::write_model(&tokenizer, "\\w+|\\s+"));
+
+        let mut flipped = bytes.clone();
+        flipped[HEADER_SIZE + 3] ^= 1;
+        for bad in [&bytes[..bytes.len() - 1], &bytes[..40], &flipped[..], b"RTOKBIN"] {
+            assert!(matches!(BinaryModel::parse(bad), Err(TokenizerError::Invalid(_))));
+        }
+    }
+}
@@ -0,0 +1,464 @@
+use core::fmt;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::TokenizerError;
+use crate::stream::Cut;
+
+// rough per entry cost of the chunk table on top of the chunk bytes
+const ENTRY_OVERHEAD: usize = 64;
+
+// spilled runs are merged into one once there are this many, so drain doesn't need too many open files
+const MAX_SPILLS: usize = 64;
+
+static SPILL_ID: AtomicUsize = AtomicUsize::new(0);
+
+// how much is read at once while looking for the end of a block
+const READ_SIZE: usize = 1 << 16;
+
+// feeds the text from reader to f in blocks of about block_size bytes, so it's never all in memory.
+// blocks end where cut says the split pattern can't match across, so splitting block by block gives
+// the same chunks as the whole text. text without such a place is kept whole, which fails once it's
+// over limit bytes, patterns other than gpt2/gpt4's are never cut. returns the number of bytes read
+pub fn read_blocks(mut reader: impl Read, block_size:usize, limit:usize, cut:&Cut, mut f: impl FnMut(&str) -> Result<(), TokenizerError>) -> Result<u64, TokenizerError> {
+    let mut block: Vec<u8> = Vec::with_capacity(block_size);
+    let mut read = vec![0u8; READ_SIZE];
+    let mut total = 0;
+    // the block has no cut before this
+    let mut checked = 0;
+    loop {
+        let n = reader.read(&mut read).map_err(TokenizerError::io("reading corpus"))?;
+        if n == 0 {
+            break;
+        }
+        total += n as u64;
+        block.extend_from_slice(&read[..n]);
+        if block.len() < block_size {
+            continue;
+        }
+        if let Some(at) = cut.find(&block, checked, &[]) {
+            f(as_text(&block[..at], total)?)?;
+            block.drain(..at);
+        } else if block.len() > limit {
+            return Err(TokenizerError::Invalid(format!("Found no place to split the corpus into blocks in the {} bytes before byte {}, which is over the memory limit. \
+                Only the gpt2 and gpt4 split patterns can be split into blocks", block.len(), total)));
+        }
+        checked = block.len();
+    }
+    if !block.is_empty() {
+        f(as_text(&block, total)?)?;
+    }
+    Ok(total)
+}
+
+fn as_text(block:&[u8], read:u64) -> Result<&str, TokenizerError> {
+    std::str::from_utf8(block).map_err(|e| TokenizerError::InvalidUtf8(format!("Corpus isn't valid utf-8 in the block before byte {}: {}", read, e)))
+}
+
+// the unique chunks training runs on with how often each occurs
+pub enum Chunks {
+    // for text that's in memory anyway
+    Bytes(Vec<(Vec<u8>, u64)>),
+    // still in the counter's table or its spills, read one at a time as training builds its sequence
+    Counted(ChunkCounter)
+}
+
+impl Chunks {
+    pub fn for_each(self, mut f: impl FnMut(&[u8], u64)) -> Result<(), TokenizerError> {
+        match self {
+            Chunks::Bytes(chunks) => {
+                chunks.into_iter().for_each(|(chunk, count)| f(&chunk, count));
+                Ok(())
+            },
+            Chunks::Counted(counter) => counter.drain(f)
+        }
+    }
+}
+
+// fnv-1a over every corpus byte in the order it's read, saved with the model to tell which corpus
+// its last training run was on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint(pub u64);
+
+impl Fingerprint {
+    pub fn of(bytes:&[u8]) -> Self {
+        let mut fingerprint = Fingerprint::default();
+        fingerprint.update(bytes);
+        fingerprint
+    }
+
+    pub fn update(&mut self, bytes:&[u8]) {
+        for b in bytes {
+            self.0 = (self.0 ^ u64::from(*b)).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+// the fingerprint of no bytes
+impl Default for Fingerprint {
+    fn default() -> Self {
+        Fingerprint(0xcbf29ce484222325)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+// adds everything read through it to the fingerprint
+pub struct Fingerprinted<'a, R> {
+    pub reader: R,
+    pub fingerprint: &'a mut Fingerprint
+}
+
+impl<R:Read> Read for Fingerprinted<'_, R> {
+    fn read(&mut self, buf:&mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.fingerprint.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+// file extensions (without the dot) kept when walking directories and patterns, an empty include keeps all
+#[derive(Debug, Default, Clone)]
+pub struct ExtensionFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>
+}
+
+impl ExtensionFilter {
+    pub fn allows(&self, path:&Path) -> bool {
+        let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        let listed = |exts:&Vec<String>| exts.iter().any(|e| e.eq_ignore_ascii_case(ext));
+        (self.include.is_empty() || listed(&self.include)) && !listed(&self.exclude)
+    }
+}
+
+// one corpus argument and the files it expanded to, each file is a document
+#[derive(Debug)]
+pub struct Source {
+    pub name: String,
+    pub files: Vec<PathBuf>
+}
+
+// sources can be files, directories (walked recursively, hidden entries skipped) or patterns with
+// * and ? inside a path component and ** for any number of directories. files named directly are
+// always kept, the filter only applies to what directories and patterns turn up
+pub fn expand_sources(args:&[String], filter:&ExtensionFilter) -> Result<Vec<Source>, TokenizerError> {
+    let mut sources = Vec::with_capacity(args.len());
+    for arg in args {
+        let path = Path::new(arg);
+        let mut files = if arg.contains(['*', '?']) {
+            glob(arg)?
+        } else if path.is_dir() {
+            let mut files = Vec::new();
+            walk(path, &mut files)?;
+            files
+        } else if path.is_file() {
+            vec![path.to_path_buf()]
+        } else {
+            return Err(TokenizerError::Invalid(format!("Corpus source {} is not a file, directory or pattern", arg)));
+        };
+        if !path.is_file() {
+            files.retain(|file| filter.allows(file));
+        }
+        if files.is_empty() {
+            return Err(TokenizerError::Invalid(format!("Corpus source {} has no files to train on", arg)));
+        }
+        files.sort();
+        sources.push(Source{ name: arg.clone(), files });
+    }
+    Ok(sources)
+}
+
+// every file below dir, in no particular order
+fn walk(dir:&Path, files:&mut Vec<PathBuf>) -> Result<(), TokenizerError> {
+    let failed = TokenizerError::io(format!("listing directory {}", dir.to_str().unwrap_or("?")));
+    for entry in fs::read_dir(dir).map_err(&failed)? {
+        let entry = entry.map_err(&failed)?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        // symlinked directories aren't followed so a link cycle can't loop forever
+        let file_type = entry.file_type().map_err(&failed)?;
+        if file_type.is_dir() {
+            walk(&path, files)?;
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn glob(pattern:&str) -> Result<Vec<PathBuf>, TokenizerError> {
+    let components: Vec<&str> = pattern.split('/').collect();
+    // the components before the first wildcard are a plain directory to start walking from
+    let fixed = components.iter().take_while(|c| !c.contains(['*', '?'])).count();
+    let base = match components[..fixed].join("/") {
+        base if base.is_empty() && pattern.starts_with('/') => "/".to_string(),
+        base if base.is_empty() => ".".to_string(),
+        base => base
+    };
+    let base = Path::new(&base);
+    if !base.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    walk(base, &mut files)?;
+    files.retain(|file| {
+        let relative: Vec<String> = file.strip_prefix(base).unwrap_or(file).components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        matches_components(&components[fixed..], &relative)
+    });
+    Ok(files)
+}
+
+fn matches_components(pattern:&[&str], path:&[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| matches_components(rest, &path[skip..])),
+        Some((first, rest)) => !path.is_empty() && wildcard(&first.chars().collect::<Vec<_>>(), &path[0].chars().collect::<Vec<_>>()) && matches_components(rest, &path[1..])
+    }
+}
+
+// * is any run of characters and ? a single one, within one path component
+fn wildcard(pattern:&[char], name:&[char]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, _) => name.is_empty(),
+        (Some(('*', rest)), _) => (0..=name.len()).any(|skip| wildcard(rest, &name[skip..])),
+        (Some((p, rest)), Some((n, name_rest))) => (*p == '?' || p == n) && wildcard(rest, name_rest),
+        (Some(_), None) => false
+    }
+}
+
+// calls f with every document of every source and prints how much each source had when verbose.
+// f returns the number of bytes it read from the document. returns the fingerprint of all of them
+pub fn read_sources(sources:&[Source], verbose:bool, mut f: impl FnMut(&Path, Fingerprinted<File>) -> Result<u64, TokenizerError>) -> Result<Fingerprint, TokenizerError> {
+    let mut fingerprint = Fingerprint::default();
+    for source in sources {
+        let mut bytes = 0;
+        for path in &source.files {
+            let file = File::open(path).map_err(TokenizerError::io(format!("opening {}", path.to_str().unwrap_or("?"))))?;
+            bytes += f(path, Fingerprinted{ reader: file, fingerprint: &mut fingerprint })?;
+        }
+        if verbose {
+            eprintln!("source {}: {} documents, {} bytes", source.name, source.files.len(), bytes);
+        }
+    }
+    Ok(fingerprint)
+}
+
+// a sorted run of the chunk table written to disk, removed once it's dropped
+struct Spill {
+    path: PathBuf
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// counts unique chunks, once the table grows past memory_limit bytes it's written to a sorted
+// run in spill_dir and cleared. the runs are merged back together by drain
+pub struct ChunkCounter {
+    counts: HashMap<Vec<u8>, u64>,
+    table_bytes: usize,
+    memory_limit: usize,
+    spill_dir: PathBuf,
+    spills: Vec<Spill>
+}
+
+impl ChunkCounter {
+    pub fn new(memory_limit:usize, spill_dir:Option<&Path>) -> Self {
+        ChunkCounter{
+            counts: HashMap::new(),
+            table_bytes: 0,
+            memory_limit,
+            spill_dir: spill_dir.map_or_else(std::env::temp_dir, Path::to_path_buf),
+            spills: Vec::new()
+        }
+    }
+
+    pub fn add(&mut self, chunk:&[u8]) -> Result<(), TokenizerError> {
+        match self.counts.get_mut(chunk) {
+            Some(count) => *count += 1,
+            None => {
+                self.table_bytes += chunk.len() + ENTRY_OVERHEAD;
+                self.counts.insert(chunk.to_vec(), 1);
+                if self.table_bytes > self.memory_limit {
+                    self.spill()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> Result<(), TokenizerError> {
+        let mut entries: Vec<(Vec<u8>, u64)> = self.counts.drain().collect();
+        entries.sort_unstable();
+        let spill = self.write_spill(entries.into_iter().map(Ok))?;
+        self.table_bytes = 0;
+        self.spills.push(spill);
+
+        if self.spills.len() >= MAX_SPILLS {
+            let spills = std::mem::take(&mut self.spills);
+            let merged = self.write_spill(MergedSpills::open(&spills)?)?;
+            self.spills.push(merged);
+        }
+        Ok(())
+    }
+
+    // entries have to come sorted by chunk
+    fn write_spill(&self, entries: impl Iterator<Item = Result<(Vec<u8>, u64), TokenizerError>>) -> Result<Spill, TokenizerError> {
+        let path = self.spill_dir.join(format!("rust_tokenizer-{}-{}.chunks", process::id(), SPILL_ID.fetch_add(1, Ordering::Relaxed)));
+        let spill = Spill{ path };
+
+        let failed = TokenizerError::io(format!("spilling chunk counts to {}", spill.path.to_str().unwrap_or("?")));
+        let mut out = BufWriter::new(File::create(&spill.path).map_err(&failed)?);
+        for entry in entries {
+            let (chunk, count) = entry?;
+            out.write_all(&(chunk.len() as u32).to_le_bytes()).map_err(&failed)?;
+            out.write_all(&chunk).map_err(&failed)?;
+            out.write_all(&count.to_le_bytes()).map_err(&failed)?;
+        }
+        out.flush().map_err(&failed)?;
+        drop(out);
+        Ok(spill)
+    }
+
+    // f gets every unique chunk with its total count. once anything spilled the rest is spilled
+    // too and f gets the chunks straight from the merge of the runs, one at a time
+    pub fn drain(mut self, mut f: impl FnMut(&[u8], u64)) -> Result<(), TokenizerError> {
+        if self.spills.is_empty() {
+            self.counts.drain().for_each(|(chunk, count)| f(&chunk, count));
+            return Ok(());
+        }
+        self.spill()?;
+        for entry in MergedSpills::open(&self.spills)? {
+            let (chunk, count) = entry?;
+            f(&chunk, count);
+        }
+        Ok(())
+    }
+}
+
+// k-way merge of sorted spills, equal chunks are summed up
+struct MergedSpills {
+    runs: Vec<BufReader<File>>,
+    heap: BinaryHeap<Reverse<(Vec<u8>, usize, u64)>>
+}
+
+impl MergedSpills {
+    fn open(spills:&[Spill]) -> Result<Self, TokenizerError> {
+        let mut merged = MergedSpills{ runs: Vec::with_capacity(spills.len()), heap: BinaryHeap::new() };
+        for (i, spill) in spills.iter().enumerate() {
+            let file = File::open(&spill.path).map_err(TokenizerError::io(format!("reading spilled chunk counts at {}", spill.path.to_str().unwrap_or("?"))))?;
+            merged.runs.push(BufReader::new(file));
+            merged.advance(i)?;
+        }
+        Ok(merged)
+    }
+
+    fn advance(&mut self, run:usize) -> Result<(), TokenizerError> {
+        if let Some((chunk, count)) = read_entry(&mut self.runs[run])? {
+            self.heap.push(Reverse((chunk, run, count)));
+        }
+        Ok(())
+    }
+
+    fn next_entry(&mut self) -> Result<Option<(Vec<u8>, u64)>, TokenizerError> {
+        let Some(Reverse((chunk, run, mut total))) = self.heap.pop() else { return Ok(None) };
+        self.advance(run)?;
+        while let Some(Reverse((next, _, _))) = self.heap.peek() {
+            if *next != chunk {
+                break;
+            }
+            let Reverse((_, run, count)) = self.heap.pop().unwrap();
+            total += count;
+            self.advance(run)?;
+        }
+        Ok(Some((chunk, total)))
+    }
+}
+
+impl Iterator for MergedSpills {
+    type Item = Result<(Vec<u8>, u64), TokenizerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+fn read_entry(run:&mut impl Read) -> Result<Option<(Vec<u8>, u64)>, TokenizerError> {
+    let failed = TokenizerError::io("reading spilled chunk counts");
+    let mut len = [0u8; 4];
+    match run.read_exact(&mut len) {
+        Ok(_) => {},
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(failed(e))
+    }
+    let mut chunk = vec![0u8; u32::from_le_bytes(len) as usize];
+    run.read_exact(&mut chunk).map_err(&failed)?;
+    let mut count = [0u8; 8];
+    run.read_exact(&mut count).map_err(&failed)?;
+    Ok(Some((chunk, u64::from_le_bytes(count))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::{compile, GPT2_SPLIT_PATTERN, GPT4_SPLIT_PATTERN};
+
+    #[test]
+    fn spilled_counts_match_in_memory() {
+        let text = "the cat sat on the mat\nthe dog sat on the log\n".repeat(50) + "a rare line\n";
+        let count = |memory_limit| {
+            let mut counter = ChunkCounter::new(memory_limit, None);
+            read_blocks(text.as_bytes(), 16, usize::MAX, &Cut::Gpt2, |block| {
+                block.split(' ').try_for_each(|chunk| counter.add(chunk.as_bytes()))
+            }).unwrap();
+            let spilled = !counter.spills.is_empty();
+            let mut counts = Vec::new();
+            Chunks::Counted(counter).for_each(|chunk, count| counts.push((chunk.to_vec(), count))).unwrap();
+            counts.sort();
+            (counts, spilled)
+        };
+        let (in_memory, spilled) = count(usize::MAX);
+        assert!(!spilled);
+        let (streamed, spilled) = count(ENTRY_OVERHEAD * 2);
+        assert!(spilled);
+        assert_eq!(streamed, in_memory);
+    }
+
+    #[test]
+    fn blocks_split_like_the_whole_text() {
+        let text = "東京は日本の首都です。\n\n    indented code(x)\n\tand tabs   runs\r\nplain line 123\n".repeat(40);
+        for pattern in [GPT2_SPLIT_PATTERN, GPT4_SPLIT_PATTERN, "[^ ]+"] {
+            let compiled = compile(pattern).unwrap();
+            let split = |text:&str| compiled.find_iter(text).map(|m| m.unwrap().as_str().to_string()).collect::<Vec<_>>();
+            let mut blocks = 0;
+            let mut chunks = Vec::new();
+            read_blocks(text.as_bytes(), 16, usize::MAX, &Cut::for_pattern(pattern), |block| {
+                blocks += 1;
+                chunks.extend(split(block));
+                Ok(())
+            }).unwrap();
+            assert_eq!(chunks, split(&text), "{:?}", pattern);
+            // only the presets are cut, and they are even without ascii on both sides of a newline
+            assert_eq!(blocks > 1, pattern != "[^ ]+", "{:?} gave {} blocks", pattern, blocks);
+        }
+        // text that can't be cut fails instead of growing past the limit
+        assert!(read_blocks(text.as_bytes(), 16, 1024, &Cut::for_pattern("[^ ]+"), |_| Ok(())).is_err());
+        assert!(read_blocks(text.as_bytes(), 16, 1024, &Cut::Gpt2, |_| Ok(())).is_ok());
+    }
+}
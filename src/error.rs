@@ -0,0 +1,89 @@
+use core::fmt;
+use std::error::Error;
+use std::io;
+
+// everything the tokenizers, training and model files can fail on. bad input always ends up as one
+// of these instead of a panic, so one bad file or id can't take down whatever embeds the tokenizer
+#[derive(Debug)]
+pub enum TokenizerError {
+    // a model or rank file that doesn't parse. line and column are 1-based, the column is where the
+    // offending entry starts if the line has several. field says what was being read
+    Parse { line: usize, column: Option<usize>, field: String, message: String },
+    // an id that's neither in the vocab nor a special token
+    UnknownId(u32),
+    // text that has to be utf-8 and isn't, the message says where
+    InvalidUtf8(String),
+    // reading or writing failed, context says what was being done
+    Io { context: String, source: io::Error },
+    // a split pattern that doesn't compile or fails on the text
+    Pattern(String),
+    // a special token found in text that doesn't allow them
+    SpecialNotAllowed(String),
+    // arguments the tokenizer can't work with, like a vocab size below 256 or clashing special token ids
+    Invalid(String)
+}
+
+impl TokenizerError {
+    pub fn parse(line:usize, field:impl Into<String>, message:impl Into<String>) -> Self {
+        TokenizerError::Parse{ line, column: None, field: field.into(), message: message.into() }
+    }
+
+    pub fn parse_at(line:usize, column:usize, field:impl Into<String>, message:impl Into<String>) -> Self {
+        TokenizerError::Parse{ line, column: Some(column), field: field.into(), message: message.into() }
+    }
+
+    // for map_err on io results, context reads after "Failed", like "reading foo.txt".
+    // it's only turned into a string once something fails
+    pub fn io(context:impl ToString) -> impl Fn(io::Error) -> Self {
+        move |source| TokenizerError::Io{ context: context.to_string(), source }
+    }
+}
+
+impl fmt::Display for TokenizerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenizerError::Parse{ line, column: None, field, message } => write!(f, "Line {}, {}: {}", line, field, message),
+            TokenizerError::Parse{ line, column: Some(column), field, message } => write!(f, "Line {}, column {}, {}: {}", line, column, field, message),
+            TokenizerError::UnknownId(id) => write!(f, "Unknown token id {}", id),
+            TokenizerError::Io{ context, source } => write!(f, "Failed {}, with {}", context, source),
+            TokenizerError::SpecialNotAllowed(token) => write!(f, "Found special token {:?} in the text but special tokens aren't allowed", token),
+            TokenizerError::InvalidUtf8(message) | TokenizerError::Pattern(message) | TokenizerError::Invalid(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl Error for TokenizerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TokenizerError::Io{ source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model;
+
+    #[test]
+    fn bad_models_and_ids_are_errors() {
+        let cases = [
+            ("", 1),
+            ("x\n0\n\n\n", 1),
+            ("257\n", 2),
+            ("257\n1\n97,98\n", 3),
+            ("257\n1\n97,98,256\n256,97,300 \n", 4),
+            ("257\n1\n97,98,256\n256,97,98\n\n257,255,255\n", 6),
+            ("257\n1\n97,98,256\n256,97,98\n\n\nvocab_size=x", 7)
+        ];
+        for (model, line) in cases {
+            match model::parse_model(model) {
+                Err(TokenizerError::Parse{ line: at, .. }) => assert_eq!(at, line, "{:?}", model),
+                other => panic!("{:?} gave {:?}", model, other.map(|_| ()))
+            }
+        }
+        let (tokenizer, _) = model::parse_model("257\n1\n97,98,256\n256,97,98\n").unwrap();
+        assert!(matches!(tokenizer.decode(vec![256, 1000]), Err(TokenizerError::UnknownId(1000))));
+    }
+}
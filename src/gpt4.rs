@@ -0,0 +1,210 @@
+use core::fmt;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::corpus::Source;
+use crate::error::TokenizerError;
+use crate::regex::{RegexTokenizer, GPT4_SPLIT_PATTERN};
+use crate::special::AllowedSpecial;
+use crate::stream::Cut;
+use crate::tiktoken::{load_ranks, recover_merges, write_ranks};
+use crate::tokenizer::{write, Format, Tokenizer};
+use crate::train::TrainOptions;
+use crate::BasicTokenizer;
+use crate::validate;
+
+pub const GPT4_SPECIAL_TOKENS: [(&str, u32); 5] = [
+    ("<|endoftext|>", 100257),
+    ("<|fim_prefix|>", 100258),
+    ("<|fim_middle|>", 100259),
+    ("<|fim_suffix|>", 100260),
+    ("<|endofprompt|>", 100276),
+];
+
+fn gpt4_special_tokens() -> HashMap<String, u32> {
+    GPT4_SPECIAL_TOKENS.iter().map(|(s, idx)| (s.to_string(), *idx)).collect()
+}
+
+// cl100k_base rebuilt from its tiktoken rank file, the ranks are used as the token ids. other rank
+// files work too with their own pattern and special tokens, see tiktoken::import.
+// the single byte tokens aren't ranked in byte order, so bytes are permuted to their rank before merging
+#[derive(Debug)]
+pub struct GPT4Tokenizer {
+    inner: RegexTokenizer,
+    byte_shuffle: Vec<u32>
+}
+
+impl GPT4Tokenizer {
+    pub fn from_tiktoken(path:&Path) -> Result<Self, TokenizerError> {
+        Self::from_ranks(load_ranks(path)?)
+    }
+
+    pub fn from_ranks(ranks:HashMap<Vec<u8>, u32>) -> Result<Self, TokenizerError> {
+        Self::from_ranks_with(ranks, GPT4_SPLIT_PATTERN, gpt4_special_tokens())
+    }
+
+    pub fn from_ranks_with(ranks:HashMap<Vec<u8>, u32>, pattern:&str, special_tokens:HashMap<String, u32>) -> Result<Self, TokenizerError> {
+        let merges = recover_merges(&ranks)?;
+
+        let mut byte_shuffle = Vec::with_capacity(256);
+        for b in 0..=255u8 {
+            match ranks.get([b].as_slice()) {
+                Some(rank) => byte_shuffle.push(*rank),
+                None => return Err(TokenizerError::Invalid(format!("Rank file has no token for the byte {}", b)))
+            }
+        }
+
+        // unlike minbpe the vocab keeps the real bytes of each rank, so decoding needs no unshuffle
+        let vocab: HashMap<u32, Vec<u8>> = ranks.into_iter().map(|(token, rank)| (rank, token)).collect();
+        let inner = BasicTokenizer{
+            trained: true,
+            vocab_size: vocab.len() as u32,
+            num_merges: merges.len() as u32,
+            merges, vocab,
+            ..BasicTokenizer::untrained()
+        };
+
+        let mut inner = RegexTokenizer::from_parts(pattern, inner)?;
+        inner.register_special_tokens(special_tokens)?;
+        Ok(GPT4Tokenizer{ inner, byte_shuffle })
+    }
+
+    pub fn encode(&self, text:&str) -> Result<Vec<u32>, TokenizerError> {
+        self.inner.encode_mapped(text, |b| self.byte_shuffle[b as usize])
+    }
+
+    pub fn encode_with_special(&self, text:&str, allowed:&AllowedSpecial) -> Result<Vec<u32>, TokenizerError> {
+        self.inner.encode_with_special_mapped(text, allowed, |b| self.byte_shuffle[b as usize])
+    }
+
+    pub fn decode(&self, ids: Vec<u32>) -> Result<String, TokenizerError> {
+        self.inner.decode(ids)
+    }
+
+    // written back as a tiktoken rank file. loading it again takes the special tokens and split
+    // pattern as gpt4's own, other ones given to tiktoken::import would be lost so they're refused
+    pub fn save_str(&self) -> Result<String, TokenizerError> {
+        if self.inner.pattern() != GPT4_SPLIT_PATTERN || self.inner.inner.special_tokens != gpt4_special_tokens() {
+            return Err(TokenizerError::Invalid("Only cl100k_base's split pattern and special tokens load back from a rank file, pass the others to tiktoken::load".to_string()));
+        }
+        Ok(write_ranks(&self.inner.inner.vocab))
+    }
+}
+
+impl fmt::Display for GPT4Tokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "GPT4Tokenizer (tiktoken ranks):")?;
+        write!(f, "{}", self.inner)
+    }
+}
+
+impl Tokenizer for GPT4Tokenizer {
+    fn encode_with_special(&self, text:&str, allowed:&AllowedSpecial) -> Result<Vec<u32>, TokenizerError> {
+        self.encode_with_special(text, allowed)
+    }
+
+    fn decode_bytes(&self, ids:&[u32]) -> Result<Vec<u8>, TokenizerError> {
+        self.inner.inner.decode_bytes(ids)
+    }
+
+    fn vocab_size(&self) -> u32 {
+        self.inner.inner.vocab_size
+    }
+
+    fn id_to_bytes(&self, id:u32) -> Option<&[u8]> {
+        self.inner.inner.id_to_bytes(id)
+    }
+
+    fn bytes_to_id(&self, bytes:&[u8]) -> Option<u32> {
+        self.inner.inner.bytes_to_id(bytes)
+    }
+
+    fn special_tokens(&self) -> &HashMap<String, u32> {
+        &self.inner.inner.special_tokens
+    }
+
+    fn add_special_tokens(&mut self, _tokens:Vec<String>) -> Result<(), TokenizerError> {
+        Err(TokenizerError::Invalid("The GPT4Tokenizer is saved as a rank file, which has no room for more special tokens".to_string()))
+    }
+
+    fn metadata(&self) -> &BTreeMap<String, String> {
+        self.inner.inner.metadata()
+    }
+
+    fn set_metadata(&mut self, _key:&str, _value:&str) -> Result<(), TokenizerError> {
+        Err(TokenizerError::Invalid("The GPT4Tokenizer is saved as a rank file, which has no room for metadata".to_string()))
+    }
+
+    fn problems(&self) -> Vec<String> {
+        validate::problems(&self.inner.inner, false)
+    }
+
+    fn cut(&self) -> Cut {
+        self.inner.cut()
+    }
+
+    // the byte tokens aren't in byte order, learn can't start from them
+    fn continue_from_sources(&mut self, _sources:&[Source], _vocab_size:u32, _verbose:Option<bool>, _options:&TrainOptions) -> Result<(), TokenizerError> {
+        Err(TokenizerError::Invalid("The GPT4Tokenizer can't be trained further".to_string()))
+    }
+
+    fn save_str(&self) -> Result<String, TokenizerError> {
+        self.save_str()
+    }
+
+    // the same rank file as save_str
+    fn to_tiktoken(&self) -> Result<String, TokenizerError> {
+        self.save_str()
+    }
+
+    // the text format is the rank file too
+    fn save_as(&self, path:&Path, format:Format) -> Result<(), TokenizerError> {
+        match format {
+            Format::Text | Format::Tiktoken => write(path, self.to_tiktoken()?.into_bytes()),
+            Format::Binary | Format::Minbpe => Err(TokenizerError::Invalid("The GPT4Tokenizer can only be saved as a rank file".to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // the rank file isn't committed, fetch it from
+    // https://openaipublic.blob.core.windows.net/encodings/cl100k_base.tiktoken
+    // into tests/fixtures or point CL100K_BASE_TIKTOKEN at it
+    fn rank_file() -> Option<PathBuf> {
+        let path = match std::env::var_os("CL100K_BASE_TIKTOKEN") {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/cl100k_base.tiktoken")
+        };
+        path.is_file().then_some(path)
+    }
+
+    // fixture lines are "ids<tab>text" with \n and \\ escaped in the text
+    fn fixtures() -> Vec<(Vec<u32>, String)> {
+        include_str!("../tests/fixtures/cl100k_base.txt").lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let (ids, text) = line.split_once('\t').unwrap();
+                let ids = ids.split(',').map(|id| id.parse::<u32>().unwrap()).collect();
+                (ids, text.replace("\\n", "\n").replace("\\\\", "\\"))
+            })
+            .collect()
+    }
+
+    // run with cargo test -- --ignored once the rank file is there
+    #[test]
+    #[ignore = "needs cl100k_base.tiktoken"]
+    fn matches_tiktoken_cl100k_base() {
+        let path = rank_file().expect("cl100k_base.tiktoken not found in tests/fixtures or CL100K_BASE_TIKTOKEN");
+        let tokenizer = GPT4Tokenizer::from_tiktoken(&path).unwrap();
+        for (ids, text) in fixtures() {
+            assert_eq!(tokenizer.encode(&text).unwrap(), ids, "encoding {:?}", text);
+            assert_eq!(tokenizer.decode(ids).unwrap(), text);
+        }
+        assert_eq!(tokenizer.decode(vec![100257]).unwrap(), "<|endoftext|>");
+        assert_eq!(tokenizer.encode_with_special("hello world<|endoftext|>", &AllowedSpecial::All).unwrap(), vec![15339, 1917, 100257]);
+    }
+}
@@ -0,0 +1,31 @@
+// byte-level bpe tokenizers after karpathy's minbpe: training, encoding, decoding and model files.
+// the cli/repl in main.rs is a thin layer on top, nothing in here prints to stdout
+
+pub mod basic;
+pub mod binary;
+pub mod corpus;
+pub mod error;
+pub mod gpt4;
+pub mod minbpe;
+pub mod model;
+pub mod regex;
+pub mod special;
+pub mod stream;
+pub mod tiktoken;
+pub mod tokenizer;
+pub mod train;
+pub mod utils;
+mod validate;
+
+// models for the tests of the model formats
+#[cfg(test)]
+mod testing;
+
+pub use basic::{merge, BasicTokenizer};
+pub use error::TokenizerError;
+pub use gpt4::GPT4Tokenizer;
+pub use regex::RegexTokenizer;
+pub use special::AllowedSpecial;
+pub use tokenizer::{Format, Tokenizer};
+pub use train::{TrainOptions, TrainParams};
+pub use utils::{count_pairs, frequent_pair, top_pairs, Ordering};
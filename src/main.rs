@@ -1,542 +1,333 @@
 use core::fmt;
-use std::borrow::{Borrow, BorrowMut};
 use std::cell::RefCell;
-use std::char::REPLACEMENT_CHARACTER;
-use std::env::args;
-use std::error::Error;
-use std::ffi::OsString;
-use std::fmt::format;
-use std::fs::read_to_string;
-use std::num::ParseIntError;
-use std::{fs, path};
-use std::io::{self, stdin, stdout, BufRead, Stdin, Write};
+use std::env;
+use std::process;
+use std::fs::{self, File};
+use std::io::{stdin, stdout, BufRead, BufWriter, Read, Stdin, Write};
 use std::path::PathBuf;
 use std::rc::Rc;
-use std::str::Bytes;
-use std::sync::Arc;
-use std::{collections::HashMap, io::Read, path::Path};
-use std::cmp::{min_by_key, Eq, Ord, Reverse};
-use std::collections::{BinaryHeap};
-use std::hash::Hash;
-
-mod utils;
-use utils::most_frequent;
-
-enum Ordering {
-    Ascending,
-    Descending
-}
+use std::path::Path;
 
-fn frequent_pair<T>(array: &[T], k: usize, ordering:Ordering) -> Vec<(usize, (&T, &T))>
-where
-    T: Hash + Eq + Ord,
-{
-    match ordering {
-        Ordering::Ascending => {
-            let mut map: HashMap<(&T, &T), usize> = HashMap::new();
-            for pair in array.iter().zip(array.iter().skip(1)) {
-                *map.entry(pair).or_default() += 1;
-            }
-        
-            let mut heap = BinaryHeap::with_capacity(k + 1);
-            for (x, count) in map.into_iter() {
-                heap.push((count, x));
-                if heap.len() > k {
-                    heap.pop();
-                }
-            }
-            return heap.into_sorted_vec().into_iter().collect();   
-        },
-        Ordering::Descending => {
-            let mut map: HashMap<(&T, &T), usize> = HashMap::new();
-            for pair in array.iter().zip(array.iter().skip(1)) {
-                *map.entry(pair).or_default() += 1;
-            }
-        
-            let mut heap = BinaryHeap::with_capacity(k + 1);
-            for (x, count) in map.into_iter() {
-                heap.push(Reverse((count, x)));
-                if heap.len() > k {
-                    heap.pop();
-                }
-            }
-            return heap.into_sorted_vec().into_iter().map(|r| r.0).collect();   
-        }
-    }
-}
+use rust_tokenizer::corpus::{expand_sources, ExtensionFilter};
+use rust_tokenizer::regex::{compile, split_pattern, GPT4_SPLIT_PATTERN};
+use rust_tokenizer::stream::{decode_stream, encode_stream};
+use rust_tokenizer::tokenizer;
+use rust_tokenizer::{AllowedSpecial, BasicTokenizer, Format, RegexTokenizer, Tokenizer, TokenizerError, TrainOptions};
 
+mod args;
+use args::{Invocation, Mode, Parsed};
 
-fn merge(ids: &Vec<u32>, pair:&(u32, u32), idx:&u32) -> Vec<u32>{
-    let mut newids: Vec<u32> = Vec::with_capacity(ids.len());
-    let mut i = 0;
-    while i < ids.len() {
-        if ids[i] == pair.0 && i < ids.len() - 1 && ids[i+1] == pair.1{
-            newids.push(idx.clone());
-            i += 2;
-        } else {
-          newids.push(ids[i].clone());
-          i +=1;  
-        }
+fn cli_parse(args:Vec<String>) -> Result<CLICommand, String>{
+    if args.len() < 2 {
+        return Ok(CLICommand::Repl());
+    }
+    let args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+    let parsed = match args::parse(&args, Mode::Cli)? {
+        Invocation::Run(parsed) => parsed,
+        Invocation::Help(help) => return Ok(CLICommand::Help(help))
+    };
+    let path = |p:&str| Path::new(p).to_owned();
+    match parsed.command.name {
+        "encode" => Ok(CLICommand::Encode(parsed.positionals.first().map(|p| path(p)), parsed.value("model").map(path).unwrap(), parsed.value("output").map(path), allowed_special(&parsed), parsed.switch("lenient"))),
+        "decode" => Ok(CLICommand::Decode(parsed.positionals.first().map(|p| path(p)), parsed.value("model").map(path).unwrap(), parsed.value("output").map(path), parsed.switch("lenient"))),
+        "train" => Ok(CLICommand::Train(train_args(&parsed)?)),
+        "check" => Ok(CLICommand::Check(path(&parsed.positionals[0]), parsed.switch("lenient"))),
+        other => Err(format!("Command {} isn't handled by the cli", other))
     }
-    return newids;
 }
 
-#[derive(Debug)]
-struct BasicTokenizer {
-    trained: bool,
+// train/continue arguments, the sources then the flags in any order
+struct TrainArgs {
+    sources: Vec<String>,
+    filter: ExtensionFilter,
+    output_path: Option<PathBuf>,
+    // none to go by the output path's extension
+    format: Option<Format>,
+    // model to continue training from
+    resume: Option<PathBuf>,
     vocab_size: u32,
-    num_merges: u32,
-
-    merges: HashMap<(u32,u32),u32>,
-    vocab: HashMap<u32, Vec<u32>>
-    //vocab: HashMap<u32, String>
+    // split pattern of a regex tokenizer, none for the basic one
+    pattern: Option<String>,
+    // registered after the merges in this order
+    special_tokens: Vec<String>,
+    // saved with the model
+    metadata: Vec<(String, String)>,
+    min_frequency: u64,
+    // none for every core
+    threads: Option<usize>,
+    // bytes
+    memory_limit: usize,
+    spill_dir: Option<PathBuf>,
+    verbose: bool
 }
 
-impl BasicTokenizer {
-    fn train(text:&String, vocab_size:u32, verbose:Option<bool>) -> Self{
-        let verbos = verbose.unwrap_or(false);
-
-        assert!(vocab_size>=256, "vocab_size has to be larger than 256");
-        let num_merges = vocab_size - 256;
-
-        let mut merges: HashMap<(u32, u32), u32> = HashMap::new();
-        let mut vocab: HashMap<u32, Vec<u32>> = HashMap::new();
-
-        let mut ids:Vec<u32> = text.chars().map(u32::from).collect();
-
-        //endianness??
-        for idx in 0..256 {
-            vocab.insert(idx, vec![idx]);  
-        }
-
-        for i in 0..num_merges{
-            //let stats = get_stats(ids, None);
-            let n;
-            let pair: (u32, u32);
-            {
-                let mf = frequent_pair(ids.as_slice(), 1, Ordering::Descending);
-                match mf.first(){
-                    Some(val) => {
-                        n = val.0;
-                        pair = (val.1.0.clone(), val.1.1.clone())
-                    },
-                    None => {
-                        println!("Break'd out of for i:{:?} in num_merges. No freq pair in ids: {:?}",i,ids);
-                        break;
-                    }
-                }
-            }
-            let idx = 256 + i;
-            ids = merge(&ids, &pair, &idx);
-
-            merges.insert(pair.to_owned(), idx);
-            //println!("inserted to merges: {:?}, {:?}\n\tmerges:{:?}\n",pair,idx,merges);
-            vocab.insert(idx, [vocab.get(&pair.0).unwrap().clone(), vocab.get(&pair.1).unwrap().clone()].concat());
-
-            if verbos {
-                println!("merge {}/{}: {:?} -> {} ({:?} had {} occurrences)", i+1, num_merges, pair, idx, vocab[&idx], n)
-            }
-
-        }
-        return BasicTokenizer{
-            trained: true,
-            vocab_size, num_merges, merges, vocab
-        };
-        
+fn train_args(parsed:&Parsed) -> Result<TrainArgs, String> {
+    let exts = |long| parsed.values(long).iter()
+        .flat_map(|value| value.split(','))
+        .map(|ext| ext.trim_start_matches('.').to_string())
+        .filter(|ext| !ext.is_empty())
+        .collect();
+    let number = |long, name| parsed.value(long).map_or(Ok(None), |value| value.parse::<u64>().map(Some).map_err(|e| format!("Invalid {} {}: {}", name, value, e)));
+
+    let mut args = TrainArgs{
+        sources: parsed.positionals.clone(),
+        filter: ExtensionFilter{ include: exts("include"), exclude: exts("exclude") },
+        output_path: parsed.value("output").map(|p| Path::new(p).to_owned()),
+        format: format_arg(parsed)?,
+        resume: parsed.value("resume").map(|p| Path::new(p).to_owned()),
+        vocab_size: 512,
+        pattern: parsed.value("pattern").map(|p| split_pattern(p).to_string()),
+        special_tokens: parsed.values("special").to_vec(),
+        metadata: parsed.values("meta").iter()
+            .map(|meta| meta.split_once('=').map(|(key, value)| (key.to_string(), value.to_string())).ok_or(format!("Metadata {:?} isn't key=value", meta)))
+            .collect::<Result<_, _>>()?,
+        min_frequency: number("min-frequency", "min frequency")?.unwrap_or(1),
+        threads: None,
+        memory_limit: TrainOptions::default().memory_limit,
+        spill_dir: parsed.value("spill-dir").map(|p| Path::new(p).to_owned()),
+        verbose: !parsed.switch("quiet")
+    };
+    if let Some(vocab_size) = number("vocab-size", "vocab size")? {
+        args.vocab_size = u32::try_from(vocab_size).map_err(|_| format!("Vocab size {} is too large", vocab_size))?;
     }
-
-    fn decode(&self, ids: Vec<u32>) -> String {
-        let mut text_bytes= Vec::new();
-        for id in ids {
-            let word = self.vocab.get(&id).unwrap().clone();
-            text_bytes = [text_bytes, word].concat();
+    if let Some(threads) = number("threads", "thread count")? {
+        match usize::try_from(threads) {
+            Ok(threads) if threads > 0 => args.threads = Some(threads),
+            _ => return Err(format!("Thread count has to be at least 1, got {}", threads))
         }
-        let text8:String = text_bytes.iter().map(|x|{
-            return char::from_u32(x.to_owned()).unwrap().to_string();
-        }).collect();
-        //let text = String::from_utf8_lossy(text8).into_owned();
-        return text8
     }
-
-    fn encode(&self, text:&String) -> Vec<u32> {
-        let mut ids:Vec<u32> = text.chars().map(u32::from).collect();
-        // println!("--- ids: {:?}", ids);
-        while ids.len() >= 2 {
-            let pair;
-            {
-                //changed from lowest to highest as in decode seems to work? why? minbpe takes the lowest or the first pair?
-                //TODO: do we need min inf, is it possible that theres no pair?
-                let stats = frequent_pair(ids.as_slice(), 1, Ordering::Descending);
-                pair = (stats.first().unwrap().1.0.clone(), stats.first().unwrap().1.1.clone());
-                // println!("got pair {:?}", pair);
-            }
-            if !self.merges.contains_key(&pair){
-                // println!("merges dont contain pair: {:?}", pair);
-                // println!("merges: {:?}", self.merges);
-                break;
-            }
-            let idx = self.merges.get(&pair).unwrap();
-            // println!("mapping {:?} -> {}", pair, idx);
-            
-            // println!("pre ids: {:?}", ids);
-            ids = merge(&ids, &pair, &idx);
-            // println!("new ids: {:?}", ids);
+    if let Some(mb) = number("memory-limit", "memory limit")? {
+        args.memory_limit = usize::try_from(mb).ok().and_then(|mb| mb.checked_mul(1 << 20)).ok_or(format!("Memory limit {} is too large", mb))?;
+    }
+    if args.vocab_size < 256 {
+        return Err(format!("Vocab size has to be at least 256, got {}", args.vocab_size));
+    }
+    match (parsed.value("type"), &args.pattern) {
+        (None, _) | (Some("regex"), Some(_)) | (Some("basic"), None) => {},
+        // gpt4's pattern unless another one is given
+        (Some("regex"), None) => args.pattern = Some(GPT4_SPLIT_PATTERN.to_string()),
+        (Some("basic"), Some(_)) => return Err("The basic tokenizer doesn't take a split pattern".to_string()),
+        (Some(other), _) => return Err(format!("Unknown tokenizer type {}, expected basic or regex", other))
+    }
+    if let Some(pattern) = &args.pattern {
+        compile(pattern).map_err(|e| e.to_string())?;
+    }
+    for (i, token) in args.special_tokens.iter().enumerate() {
+        if token.is_empty() || args.special_tokens[..i].contains(token) {
+            return Err(format!("Special token {:?} is empty or given twice", token));
         }
-        return ids
     }
+    // saving would fail too, but only after training
+    if args.format() == Format::Tiktoken && (args.pattern.is_some() || !args.special_tokens.is_empty() || !args.metadata.is_empty()) {
+        return Err("A tiktoken rank file can't keep a split pattern, special tokens or metadata".to_string());
+    }
+    Ok(args)
+}
 
-    // format: split by lines 
-    // vocab size
-    // num_merges
-    // merges seperated by ' ' then ',' first two -> 3rd
-    // vocab seperated by ' ' then ',' first -> rest
-    fn save(&self, path:&Path) -> Result<(), io::Error> {
-        let mut model = String::new();
-        model.push_str(format!("{}\n", self.vocab_size).as_str());
-        model.push_str(format!("{}\n", self.num_merges).as_str());
-        for merge in self.merges.borrow() {            
-            model.push_str(format!("{},{},{} ", merge.0.0, merge.0.1, merge.1).as_str());
-        }
-        model.push('\n');
-        for voc in self.vocab.borrow() {
-            model.push_str(format!("{}", voc.0).as_str());
-            for x in voc.1 {
-                model.push_str(format!(",{}", x).as_str());
-            }
-            model.push(' ');
-        }
-//        model.push_str(format!("{:?}\n", self.merges).as_str());
-//        model.push_str(format!("{:?}\n", self.vocab).as_str());
+fn allowed_special(parsed:&Parsed) -> AllowedSpecial {
+    AllowedSpecial::from_arg(parsed.value("allowed-special").unwrap())
+}
 
-        println!("writing model as:\n{}", model);
+fn format_arg(parsed:&Parsed) -> Result<Option<Format>, String> {
+    parsed.value("format").map(|name| Format::from_name(name).ok_or(format!("Unknown model format {}, expected text, binary, minbpe or tiktoken", name))).transpose()
+}
 
-        return fs::write(path, model);
+impl TrainArgs {
+    // none for stdout
+    fn output(&self) -> Option<&Path> {
+        self.output_path.as_deref().filter(|path| !is_std(Some(path)))
     }
 
-    fn save_str(&self) -> String {
-        let mut model = String::new();
-        model.push_str(format!("{}\n", self.vocab_size).as_str());
-        model.push_str(format!("{}\n", self.num_merges).as_str());
-        for merge in self.merges.borrow() {            
-            model.push_str(format!("{},{},{} ", merge.0.0, merge.0.1, merge.1).as_str());
-        }
-        model.push('\n');
-        for voc in self.vocab.borrow() {
-            model.push_str(format!("{}", voc.0).as_str());
-            for x in voc.1 {
-                model.push_str(format!(",{}", x).as_str());
-            }
-            model.push(' ');
-        }
-
-        return model;
+    fn format(&self) -> Format {
+        self.format.unwrap_or(self.output().map_or(Format::Text, Format::from_path))
     }
 
-    fn load(path:&Path) -> Result<Self, String> {
-        if let Ok(text) = fs::read_to_string(&path) {
-            let lines:Vec<&str> = text.split('\n').collect();
-            let vocab_size = lines.get(0).unwrap().parse::<u32>().unwrap();
-            let num_merges = lines.get(1).unwrap().parse::<u32>().unwrap();
-            let mut new_merges: HashMap<(u32,u32), u32> = HashMap::new(); 
-            for merge in lines.get(2).unwrap().split(' '){
-                let mut elems = merge.split(',');
-                if let Ok(a) = elems.next().unwrap().parse::<u32>(){
-                    if let Ok(b) = elems.next().unwrap().parse::<u32>(){
-                        if let Ok(c) = elems.next().unwrap().parse::<u32>(){
-                            //println!("parsed merge ({},{}) -> {}", a,b,c);
-                            new_merges.insert((a,b), c);
-                        } else { break; }                            
-                    } else { break; }
-                } else { break; }
-            }
-            let mut new_vocab: HashMap<u32, Vec<u32>> = HashMap::new();
-            for voc in lines.get(3).unwrap().split(' '){
-                let mut elems = voc.split(',');
-                if let Ok(a) = elems.next().unwrap().parse::<u32>(){
-                    let rv: Vec<Result<u32, String>> = elems
-                    .map(|el|
-                        match el.parse::<u32>(){
-                            Ok(o) => {return Ok(o)},
-                            Err(e) => {return Err(format!("Error parsing vocab ids: {}", el))}
-                        })
-                    .collect();
-                    let v = rv.iter().map(|f|f.to_owned().unwrap()).collect();
-                    //println!("parsed vocab {}, {:#?}",a,v);
-                    new_vocab.insert(a, v);
-                } else { break; }
-            }
-            return Ok(BasicTokenizer{vocab_size, trained:true, num_merges, merges:new_merges, vocab:new_vocab});
-        }else {
-            return Err(format!("Failed reading model from path: {}", path.to_str().unwrap()));
+    fn options(&self) -> TrainOptions {
+        let defaults = TrainOptions::default();
+        TrainOptions{
+            threads: self.threads.unwrap_or(defaults.threads),
+            min_frequency: self.min_frequency,
+            memory_limit: self.memory_limit,
+            spill_dir: self.spill_dir.clone(),
+            ..defaults
         }
-
     }
-    fn load_mut(&mut self, path:&Path) -> Result<(), String> { 
-        todo!()
-    }
-}
 
-impl fmt::Display for BasicTokenizer {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut out = Vec::new();
-        write!(out,"BasicTokenizer:\n\t   trained: {}\n\tvocab_size: {}\n\tnum_merges: {}\n\tmerges:\n",self.trained,self.vocab_size,self.num_merges).unwrap();
-
-        for elem in self.merges.borrow(){
-            write!(out, "\t\t({:4},{:4}) -> {}\n", elem.0.0, elem.0.1, elem.1).unwrap();
+    fn set_metadata(&self, model:&mut dyn Tokenizer) -> Result<(), CliError> {
+        for (key, value) in &self.metadata {
+            model.set_metadata(key, value).map_err(|e| CliError::Args(e.to_string()))?;
         }
+        Ok(())
+    }
+}
 
-        write!(out,"\tvocab:\n", ).unwrap();
-
-        for voc in self.vocab.borrow(){
-            write!(out, "\t\t{:<4} : {:?}\n", voc.0, voc.1).unwrap()
-        }
+// the pattern and special tokens are checked by train_args, what's left failing is reading the corpus
+fn run_train(args:&TrainArgs) -> Result<Box<dyn Tokenizer>, CliError> {
+    let sources = expand_sources(&args.sources, &args.filter).map_err(|e| CliError::Io(e.to_string()))?;
+    let mut model: Box<dyn Tokenizer> = match &args.pattern {
+        Some(pattern) => Box::new(RegexTokenizer::train_from_sources(&sources, args.vocab_size, Some(args.verbose), pattern, &args.options()).map_err(|e| CliError::Io(e.to_string()))?),
+        None => Box::new(BasicTokenizer::train_from_sources(&sources, args.vocab_size, Some(args.verbose), &args.options()).map_err(|e| CliError::Io(e.to_string()))?)
+    };
+    model.add_special_tokens(args.special_tokens.clone()).map_err(|e| CliError::Args(e.to_string()))?;
+    args.set_metadata(model.as_mut())?;
+    Ok(model)
+}
 
-        return write!(f, "{}", String::from_utf8(out).unwrap());
+// the model keeps its type and split pattern
+fn run_continue(args:&TrainArgs, model:&mut dyn Tokenizer) -> Result<(), CliError> {
+    if args.pattern.is_some() {
+        return Err(CliError::Args("Continued training keeps the model's tokenizer type and split pattern".to_string()));
     }
+    let sources = expand_sources(&args.sources, &args.filter).map_err(|e| CliError::Io(e.to_string()))?;
+    // a vocab size the model can't grow to is checked before anything is read
+    model.continue_from_sources(&sources, args.vocab_size, Some(args.verbose), &args.options()).map_err(|e| match e {
+        TokenizerError::Invalid(e) => CliError::Args(e),
+        e => CliError::Io(e.to_string())
+    })?;
+    model.add_special_tokens(args.special_tokens.clone()).map_err(|e| CliError::Args(e.to_string()))?;
+    args.set_metadata(model)
 }
 
-fn cli_parse(args:Vec<String>) -> Result<CLICommand, String>{
-    if let Some(cmd) = args.get(1){
-        let mut skip_dash = 0;
-        for ch in cmd.chars() {
-            if ch == '-' {
-                skip_dash += 1;
-            } else {
-                break
-            }
-        }
+// no path or - means stdin/stdout
+fn is_std(path:Option<&Path>) -> bool {
+    path.is_none_or(|path| path == Path::new("-"))
+}
 
-        match &cmd[skip_dash..] {
-            "e"|"enc"|"encode" => {
-                match (args.get(2), args.get(3), args.get(4)) {
-                    (Some(text_path), Some(model_path), Some(output_path)) => {
-                        return Ok(CLICommand::Encode(Path::new(text_path).to_owned(), Path::new(model_path).to_owned(), Some(Path::new(output_path).to_owned())))
-                    },
-                    (Some(text_path), Some(model_path), None) => {
-                        return Ok(CLICommand::Encode(Path::new(text_path).to_owned(), Path::new(model_path).to_owned(), None))
-                    }, 
-                    _ => {
-                        return Err(format!("Failed to parse arguments for cmd: {}", cmd))
-                    }
-                }
-            },
-            "d"|"dec"|"decode" => {
-                match (args.get(2), args.get(3), args.get(4)) {
-                    (Some(text_path), Some(model_path), Some(output_path)) => {
-                        return Ok(CLICommand::Decode(Path::new(text_path).to_owned(), Path::new(model_path).to_owned(), Some(Path::new(output_path).to_owned())))
-                    },
-                    (Some(text_path), Some(model_path), None) => {
-                        return Ok(CLICommand::Decode(Path::new(text_path).to_owned(), Path::new(model_path).to_owned(), None))
-                    }, 
-                    _ => {
-                        return Err(format!("Failed to parse arguments for cmd: {}", cmd))
-                    }
-                }
-            },
-            "t"|"tr"|"train" => {
-                match (args.get(2), args.get(3)) {
-                    (Some(text_path), Some(output_path)) => {
-                        return Ok(CLICommand::Train(Path::new(text_path).to_owned(), Some(Path::new(output_path).to_owned())))
-                    },
-                    (Some(text_path), None) => {
-                        return Ok(CLICommand::Train(Path::new(text_path).to_owned(), None))
-                    }, 
-                    _ => {
-                        return Err(format!("Failed to parse arguments for cmd: {}", cmd))
-                    }
-                }
-            },
-            _ => {
-                return Err(format!("Failed to parse cmd: {} as a cli command.", cmd));
-            }
-        }
-    } else {
-        return Ok(CLICommand::REPL());
+fn open_input(input:Option<&Path>) -> Result<Box<dyn Read>, String> {
+    match input {
+        Some(path) if !is_std(Some(path)) => Ok(Box::new(File::open(path).map_err(|e| format!("Failed opening {}, with {}", path.to_str().unwrap_or("?"), e))?)),
+        _ => Ok(Box::new(stdin().lock()))
     }
 }
 
-// todo 
-//    cli argparse:
-//          accept options with - and -- as well by simply ignoring starting dashes
-//          ./app [-e|e|enc|encode] ./path.txt ./path.model ./path.ids(default stdout)
-//          ./app [-d|d|dec|decode] ./path.ids ./path.model ./path.txt(default stdout)
-//          ./app [-t|t|tr|train] ./path.txt ./path.model(default stdout)
-//       not implemented
-//          ./app [-e|e|enc|encode] ./path.txt [-m|m|mod|model] ./path.model ?([-o|o|out|output] ./path.ids(default stdout))
-//          ./app [-d|d|dec|decode] ./path.ids [-m|m|mod|model] ./path.model ?([-o|o|out|output] ./path.txt(default stdout))
-//          ./app [-t|t|tr|train] ./path.txt ?([-o|o|out|output] ./path.model(default stdout))
-// todo
-//    repl: [e|enc|encode] ./path.txt (in) ?([-o|o|out|output] ./out.ids)
-//          [d|dec|decode] ./path.ids (in) ?([-o|o|out|output] ./out.txt)
-//          [t|tr|train] ./path.txt (in)
-//          [p|pr|print] 
-//          [l|ld|load] ./path.model (in)
-//          [s|sv|save] ./path.model (out)
+fn open_output(output:Option<&Path>) -> Result<Box<dyn Write>, String> {
+    match output {
+        Some(path) if !is_std(Some(path)) => Ok(Box::new(BufWriter::new(File::create(path).map_err(|e| format!("Failed creating {}, with {}", path.to_str().unwrap_or("?"), e))?))),
+        _ => Ok(Box::new(BufWriter::new(stdout().lock())))
+    }
+}
 
 fn main() {
-    let args:Vec<String> = args().collect();
-    println!("Got args: {:?}", args);
-
-    let cli = cli_parse(args);
-    match cli {
-        Ok(CLICommand::Decode(text_path, model_path, output_path)) => {
-            match BasicTokenizer::load(&model_path.as_path()){
-                Ok(model)=>{
-                    //TODO: move to its own function, repeats in repl as well
-                    if let Ok(text) = fs::read_to_string(&text_path) {
-                        let ids: Result<Vec<u32>, String> = text.split(',').map(|number|match number.parse::<u32>(){
-                            Ok(o) => {return Ok(o)},
-                            Err(err) => {Err(format!("Couldn't parse the file for ids with err: {}. Expected format is comma seperated numbers: 1,2,3,4,... instead got: {}", err, number))},
-                        }).collect();
-                        match ids {
-                            Ok(o) => {
-                                //let ids = text.split(',').map(|number|match number.parse::<u32>()?{}).collect();
-                                let result = model.decode(o);
-
-                                if let Some(output) = output_path {
-                                    match fs::write(&output, result){
-                                        Ok(_) => {},
-                                        Err(e) => {println!("Failed writing to output path: {}, with {}",output.to_str().unwrap_or("?"), e);}
-                                    }
-                                } else {
-                                    println!("{}",result);
-                                } 
-                            }
-                            Err(e) => {
-                                println!("{}",e);
-                                return;
-                            },
-                        }
-                    } else {
-                        print!("Couldn't read file at {:?}", text_path);  
-                    }
-                },
-                Err(e)=>{
-                    println!("Failed loading the model at {}, with: {}", model_path.to_str().unwrap_or("?"), e);
-                }
-            }
+    let args:Vec<String> = env::args().collect();
+    if let Err(e) = run(args) {
+        eprintln!("{}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+// the cli, errors are left to main so stdout only ever gets the output asked for
+fn run(args:Vec<String>) -> Result<(), CliError> {
+    match cli_parse(args).map_err(CliError::Args)? {
+        CLICommand::Decode(input, model_path, output, lenient) => {
+            let model = load_model(&model_path, lenient)?;
+            let reader = open_input(input.as_deref()).map_err(CliError::Io)?;
+            let writer = open_output(output.as_deref()).map_err(CliError::Io)?;
+            decode_stream(reader, writer, |ids| model.decode_bytes(ids)).map_err(|e| match e {
+                TokenizerError::Io{..} => CliError::Io(e.to_string()),
+                e => CliError::Decode(format!("Failed decoding, with {}", e))
+            })
         },
-        Ok(CLICommand::Encode(text_path, model_path, output_path)) => {
-            match BasicTokenizer::load(&model_path.as_path()){
-                Ok(model)=>{
-                    match fs::read_to_string(&text_path) {
-                        Ok(text) => {
-                            let result = model.encode(&text);
-                            match output_path {
-                                Some(output_p) => {
-                                    let mut output: String = "".to_string();
-                                    for r in result {
-                                        output.push_str(format!("{},",r).as_str())
-                                    }
-                                    output.pop();
-                                    match fs::write(&output_p, output){
-                                        Ok(_) => {},
-                                        Err(e) => {println!("Failed writing to output at {}, with {}", output_p.to_str().unwrap_or("?"), e)}
-                                    }
-                                },
-                                None => {
-                                    let mut output: String = "".to_string();
-                                    for r in result {
-                                        output.push_str(format!("{},",r).as_str())
-                                    }
-                                    output.pop();
-                                    println!("{}", output);
-                                }
-                            }
-                        },
-                        Err(e) => {println!("Failed reading the file at {}, with {}", text_path.to_str().unwrap_or("?"), e)}
-                    }
-                },
-                Err(e)=>{
-                    println!("Failed loading the model at {}, with: {}", model_path.to_str().unwrap_or("?"), e);
-                }
+        CLICommand::Encode(input, model_path, output, allowed, lenient) => {
+            let model = load_model(&model_path, lenient)?;
+            let specials: Vec<&[u8]> = model.special_tokens().keys().map(|s| s.as_bytes()).collect();
+            let reader = open_input(input.as_deref()).map_err(CliError::Io)?;
+            let mut writer = open_output(output.as_deref()).map_err(CliError::Io)?;
+            encode_stream(reader, &mut writer, &model.cut(), &specials, |text| model.encode_with_special(text, &allowed)).map_err(|e| match e {
+                TokenizerError::Io{..} => CliError::Io(e.to_string()),
+                e => CliError::Encode(format!("Failed encoding, with {}", e))
+            })?;
+            if is_std(output.as_deref()) {
+                writeln!(writer).and_then(|_| writer.flush()).map_err(|e| CliError::Io(format!("Failed writing ids with {}", e)))?;
             }
+            Ok(())
         },
-        Ok(CLICommand::Train(text_path, output_path)) => {
-            match fs::read_to_string(&text_path){
-                Ok(text) => {
-                    let result = BasicTokenizer::train(&text, 512, Some(true));
-                    match output_path {
-                        Some(output_p) => {
-                            result.save(&output_p);
-                        },
-                        None => {
-                            println!("{}", result.save_str());
-                        }
-                    }
+        CLICommand::Train(args) => {
+            let model = match &args.resume {
+                Some(path) => {
+                    let mut model = load_model(path, false)?;
+                    run_continue(&args, model.as_mut())?;
+                    model
                 },
-                Err(e) => {println!("Failed reading file at {}, with {}", text_path.to_str().unwrap_or("?"), e)}
+                None => run_train(&args)?
+            };
+            // a format the model can't be saved in is the arguments' fault
+            let failed = |e:TokenizerError| match e {
+                TokenizerError::Invalid(e) => CliError::Args(e),
+                e => CliError::Io(e.to_string())
+            };
+            let written = match (args.output(), args.format()) {
+                (Some(path), format) => return model.save_as(path, format).map_err(failed),
+                (None, Format::Text) => writeln!(stdout(), "{}", model.save_str().map_err(failed)?),
+                (None, Format::Binary) => stdout().write_all(&model.to_binary().map_err(failed)?).and_then(|_| stdout().flush()),
+                (None, Format::Tiktoken) => write!(stdout(), "{}", model.to_tiktoken().map_err(failed)?),
+                (None, Format::Minbpe) => return Err(CliError::Args("minbpe's format is a .model and a .vocab file, it needs --output".to_string()))
+            };
+            written.map_err(|e| CliError::Io(format!("Failed writing model, with {}", e)))
+        },
+        // the problems go to stdout, one per line, so they can be piped like any other output. a
+        // lenient check lists the skipped entries first, a model is only consistent without any
+        CLICommand::Check(path, lenient) => {
+            let failed = |e:TokenizerError| CliError::Model(format!("Failed loading the model at {}, with: {}", path.to_str().unwrap_or("?"), e));
+            let (model, warnings) = match lenient {
+                true => tokenizer::load_lenient(&path).map_err(failed)?,
+                false => (tokenizer::load_unchecked(&path).map_err(failed)?, Vec::new())
+            };
+            let problems: Vec<String> = warnings.iter().map(|warning| format!("warning: {}", warning)).chain(model.problems()).collect();
+            let mut out = stdout().lock();
+            for problem in &problems {
+                writeln!(out, "{}", problem).map_err(|e| CliError::Io(format!("Failed writing problems, with {}", e)))?;
             }
-
+            match problems.len() {
+                0 => writeln!(out, "{} is consistent", path.to_str().unwrap_or("?")).map_err(|e| CliError::Io(format!("Failed writing, with {}", e))),
+                n => Err(CliError::Model(format!("Model at {} has {} problem{}", path.to_str().unwrap_or("?"), n, if n == 1 { "" } else { "s" })))
+            }
+        },
+        CLICommand::Help(help) => {
+            println!("{}", help);
+            Ok(())
         },
-        Ok(CLICommand::REPL()) => {
-            println!("repl usage: \n\t{}", usage());
+        CLICommand::Repl() => {
+            println!("{}", args::usage(Mode::Repl));
         
-            let model:Rc<RefCell<Option<BasicTokenizer>>> = Rc::new(RefCell::new(Option::None));
+            let model:Rc<RefCell<Option<Box<dyn Tokenizer>>>> = Rc::new(RefCell::new(Option::None));
             let stdin = stdin();
         
-            //TODO: use
-            let not_exit = true;
-            while not_exit{
-                get_cmd(&stdin, model.clone())
-            }
-        },
-        Err(e) => {
-            println!("Failed parsing CLI command with: {}\n{}", e, cli_usage());
+            while get_cmd(&stdin, model.clone()) {}
+            Ok(())
         }
     }
-
-
 }
 
-//ugly
-fn usage() -> String {
-    return "commands:
-    \t[e|enc|encode] ./path.txt (in)
-    \t[d|dec|decode] ./path.ids (in)
-    \t[t|tr|train] ./path.txt (in)
-    \t[l|ld|load] ./path.model (in)
-    \t[s|sv|save] ./path.model (out)
-    \t[p|pr|print]".to_string();
-}
-
-fn cli_usage() -> String {
-    return "cli usage:
-    \t./app [-e|e|enc|encode] ./path.txt ./path.model ./path.ids(default stdout)
-    \t./app [-d|d|dec|decode] ./path.ids ./path.model ./path.txt(default stdout)
-    \t./app [-t|t|tr|train] ./path.txt ./path.model(default stdout)
-    \t./app -> REPL mode
-    ".to_string();
+// a lenient load prints what it skipped to stderr, the model still has to validate
+fn load_model(path:&Path, lenient:bool) -> Result<Box<dyn Tokenizer>, CliError> {
+    let failed = |e:TokenizerError| CliError::Model(format!("Failed loading the model at {}, with: {}", path.to_str().unwrap_or("?"), e));
+    if !lenient {
+        return tokenizer::load(path).map_err(failed);
+    }
+    let (model, warnings) = tokenizer::load_lenient(path).map_err(failed)?;
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    model.validate().map_err(failed)?;
+    Ok(model)
 }
 
-fn get_cmd(stdin:&Stdin, mut model:Rc<RefCell<Option<BasicTokenizer>>>){
+// false once stdin is closed or can't be read
+fn get_cmd(stdin:&Stdin, model:Rc<RefCell<Option<Box<dyn Tokenizer>>>>) -> bool {
     let mut line = String::new();
     print!("repl> ");
-    stdout().flush();
-    stdin.lock().read_line(&mut line).unwrap();
+    let _ = stdout().flush();
+    if !matches!(stdin.lock().read_line(&mut line), Ok(n) if n > 0) {
+        return false;
+    }
 
     match parse_line(&line) {
         Ok(REPLCommand::Decode(path)) => {
             match (*model).borrow().as_ref() {
                 Some(tokenizer) => {
-                    if let Ok(text) = fs::read_to_string(&path) {
-                        let ids: Result<Vec<u32>, String> = text.split(',').map(|number|match number.parse::<u32>(){
-                            Ok(o) => {return Ok(o)},
-                            Err(err) => {Err(format!("Couldn't parse the file for ids with err: {}. Expected format is comma seperated numbers: 1,2,3,4,... instead got: {}", err, number))},
-                        }).collect();
-                        match ids {
-                            Ok(o) => {
-                                //let ids = text.split(',').map(|number|match number.parse::<u32>()?{}).collect();
-                                let result = tokenizer.decode(o);
-                                println!("result:\n\t{}",result);
-                            }
-                            Err(e) => {
-                                println!("{}",e);
-                                return;
-                            },
-                        }
-                    } else {
-                        print!("Couldn't read file at {:?}", path);  
+                    match repl_decode(&path, tokenizer.as_ref()) {
+                        Ok(result) => println!("result:\n\t{}", result),
+                        Err(e) => println!("{}", e)
                     }
                 }
                 None => {
@@ -544,12 +335,14 @@ fn get_cmd(stdin:&Stdin, mut model:Rc<RefCell<Option<BasicTokenizer>>>){
                 }
             }
         },
-        Ok(REPLCommand::Encode(path)) => {
+        Ok(REPLCommand::Encode(path, allowed)) => {
             match (*model).borrow().as_ref() {
                 Some(tokenizer) => {
                     if let Ok(text) = fs::read_to_string(&path) {
-                        let result = tokenizer.encode(&text);
-                        println!("result:\n\t{:?}",result);
+                        match tokenizer.encode_with_special(&text, &allowed) {
+                            Ok(result) => println!("result:\n\t{:?}",result),
+                            Err(e) => println!("{}", e)
+                        }
                     } else {
                         print!("Couldn't read file at {:?}", path);  
                     }                
@@ -559,13 +352,39 @@ fn get_cmd(stdin:&Stdin, mut model:Rc<RefCell<Option<BasicTokenizer>>>){
                 }
             }
         },
-        Ok(REPLCommand::Train(path)) => {
-            if let Ok(text) = fs::read_to_string(&path) {
-                let result = BasicTokenizer::train(&text, 512, Some(true));
-                println!("result:\nmerges: {:?}\nvocab: {:?}", result.merges, result.vocab);
-                *(*model).borrow_mut() = Some(result);
-            } else {
-                println!("Couldn't read file at {:?}", path);  
+        Ok(REPLCommand::Train(args)) => {
+            match run_train(&args) {
+                Ok(result) => {
+                    println!("result:\n{}", result);
+                    *(*model).borrow_mut() = Some(result);
+                },
+                Err(e) => {println!("Failed training, with {}", e)}
+            }
+        },
+        Ok(REPLCommand::Continue(args)) => {
+            match (*model).borrow_mut().as_mut() {
+                Some(tokenizer) => {
+                    match run_continue(&args, tokenizer.as_mut()) {
+                        Ok(_) => println!("model now has {} tokens", tokenizer.vocab_size()),
+                        Err(e) => println!("Failed training, with {}", e)
+                    }
+                },
+                None => {
+                    println!("Model is not initialized, train or load first")
+                }
+            }
+        },
+        Ok(REPLCommand::Special(tokens)) => {
+            match (*model).borrow_mut().as_mut() {
+                Some(model) => {
+                    match model.add_special_tokens(tokens) {
+                        Ok(_) => println!("special tokens:\n\t{:?}", model.special_tokens()),
+                        Err(e) => println!("Failed registering special tokens with: {}", e)
+                    }
+                },
+                None => {
+                    println!("Model is not initialized, train or load first")
+                }
             }
         },
         Ok(REPLCommand::Print()) => {
@@ -578,11 +397,11 @@ fn get_cmd(stdin:&Stdin, mut model:Rc<RefCell<Option<BasicTokenizer>>>){
                 }
             }
         },
-        Ok(REPLCommand::Save(path)) => {
+        Ok(REPLCommand::Save(path, format)) => {
             match (*model).borrow().as_ref() {
                 Some(tokenizer) => {
                     println!("Writing model to path: {}\n", path.to_str().unwrap());
-                    match tokenizer.save(&path) {
+                    match tokenizer.save_as(&path, format.unwrap_or(Format::from_path(&path))) {
                         Ok(_) => {},
                         Err(e) => {println!("Failed writing with: {}", e)}   
                     }
@@ -592,89 +411,171 @@ fn get_cmd(stdin:&Stdin, mut model:Rc<RefCell<Option<BasicTokenizer>>>){
                 }
             }
         },
-        Ok(REPLCommand::Load(path)) => {
+        Ok(REPLCommand::Load(path, lenient)) => {
             println!("Loading model from path: {}\n", path.to_str().unwrap());
-            match BasicTokenizer::load(&path) {
+            let loaded = match lenient {
+                true => tokenizer::load_lenient(&path).and_then(|(tokenizer, warnings)| {
+                    for warning in warnings {
+                        println!("Warning: {}", warning);
+                    }
+                    tokenizer.validate().map(|_| tokenizer)
+                }),
+                false => tokenizer::load(&path)
+            };
+            match loaded {
                 Ok(new_tok) => {
                     *(*model).borrow_mut() = Some(new_tok);
                 },
                 Err(e) => {println!("Failed loading with: {}", e)}   
             }
         },
+        Ok(REPLCommand::Help(help)) => {
+            println!("{}", help)
+        },
         Err(err) => {
             println!("{}",err)
         }
     }
+    true
 }
         
 
 
+// ids in the same format decode reads, like a file written by encode, line breaks and all
+fn repl_decode(path:&Path, tokenizer:&dyn Tokenizer) -> Result<String, String> {
+    let file = File::open(path).map_err(|e| format!("Couldn't read file at {:?}, with {}", path, e))?;
+    let mut text = Vec::new();
+    decode_stream(file, &mut text, |ids| tokenizer.decode_bytes(ids)).map_err(|e| format!("Failed decoding, with {}", e))?;
+    Ok(String::from_utf8_lossy(&text).into_owned())
+}
+
 fn parse_line(line:&str) -> Result<REPLCommand, String>{
     let args:Vec<&str> = line.split_whitespace().collect();
-
-    // if !path.is_file(){
-    //     return Err(format!("Parsed path isn't a file: {}\n\t{}", path.to_str().unwrap_or(""), path.));
-    // }
-        
-    match args[0] {
-        "e"|"enc"|"encode" => {
-            if let Some(path) = args.get(1){
-                return Ok(REPLCommand::Encode(Path::new(path.trim()).to_owned()))
-            } else {
-                return Err(format!("Not enough arguments for command {:?}\n{}", args, usage()))
-            }
-        },
-        "d"|"dec"|"decode" => {
-            if let Some(path) = args.get(1){
-                return Ok(REPLCommand::Decode(Path::new(path.trim()).to_owned()))
-            } else {
-                return Err(format!("Not enough arguments for command {:?}\n{}", args, usage()))
-            }
-            },
-        "t"|"tr"|"train" => {
-            if let Some(path) = args.get(1){
-                return Ok(REPLCommand::Train(Path::new(path.trim()).to_owned()))
-            } else {
-                return Err(format!("Not enough arguments for command {:?}\n{}", args, usage()))
-            }
-            }
-        "p"|"pr"|"print" => {
-            return Ok(REPLCommand::Print())
-        },
-        "s"|"sv"|"save" => {
-            if let Some(path) = args.get(1){
-                return Ok(REPLCommand::Save(Path::new(path.trim()).to_owned()))
-            } else {
-                return Err(format!("Not enough arguments for command {:?}\n{}", args, usage()))
-            }                
-        },
-        "l"|"ld"|"load" => {
-            if let Some(path) = args.get(1){
-                return Ok(REPLCommand::Load(Path::new(path.trim()).to_owned()))
-            } else {
-                return Err(format!("Not enough arguments for command {:?}\n{}", args, usage()))
-            }                
-        },
-        _ => {
-            return Err(format!("Couldn't parse \"{}\" into a command, expected: {}", args[0], usage()))
-        }
+    if args.is_empty() {
+        return Ok(REPLCommand::Help(args::usage(Mode::Repl)));
+    }
+    let parsed = match args::parse(&args, Mode::Repl)? {
+        Invocation::Run(parsed) => parsed,
+        Invocation::Help(help) => return Ok(REPLCommand::Help(help))
+    };
+    let path = || Path::new(&parsed.positionals[0]).to_owned();
+    match parsed.command.name {
+        "encode" => Ok(REPLCommand::Encode(path(), allowed_special(&parsed))),
+        "decode" => Ok(REPLCommand::Decode(path())),
+        "train" => Ok(REPLCommand::Train(train_args(&parsed)?)),
+        "continue" => Ok(REPLCommand::Continue(train_args(&parsed)?)),
+        "special" => Ok(REPLCommand::Special(parsed.positionals.clone())),
+        "print" => Ok(REPLCommand::Print()),
+        "save" => Ok(REPLCommand::Save(path(), format_arg(&parsed)?)),
+        "load" => Ok(REPLCommand::Load(path(), parsed.switch("lenient"))),
+        other => Err(format!("Command {} isn't handled by the repl", other))
     }
 }
 
 enum REPLCommand  {
-    Encode(PathBuf),
+    Encode(PathBuf, AllowedSpecial),
     Decode(PathBuf),
-    Train(PathBuf),
+    Train(TrainArgs),
+    Continue(TrainArgs),
+    Special(Vec<String>),
     Print(),
-    Save(PathBuf),
-    Load(PathBuf)
+    Save(PathBuf, Option<Format>),
+    Load(PathBuf, bool),
+    Help(String)
+}
+
+// what a cli run failed on, each kind exits with its own code
+enum CliError {
+    // arguments or flags that don't make sense
+    Args(String),
+    // input, corpus or output that can't be read or written
+    Io(String),
+    // a model file that can't be loaded
+    Model(String),
+    // ids that aren't numbers or aren't in the model
+    Decode(String),
+    // text that isn't utf-8 or has special tokens in it
+    Encode(String)
+}
+
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Args(_) => 2,
+            CliError::Io(_) => 3,
+            CliError::Model(_) => 4,
+            CliError::Decode(_) => 5,
+            CliError::Encode(_) => 6
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Args(e) | CliError::Io(e) | CliError::Model(e) | CliError::Decode(e) | CliError::Encode(e) => write!(f, "{}", e)
+        }
+    }
 }
 
 enum CLICommand {
-    //     text   , model  , output
-    Encode(PathBuf, PathBuf, Option<PathBuf>),
-    Decode(PathBuf, PathBuf, Option<PathBuf>),
-    //    text   , output
-    Train(PathBuf, Option<PathBuf>),
-    REPL()
-}
\ No newline at end of file
+    //     input (stdin), model  , output (stdout), which special tokens, lenient
+    Encode(Option<PathBuf>, PathBuf, Option<PathBuf>, AllowedSpecial, bool),
+    //     input (stdin), model  , output (stdout), lenient
+    Decode(Option<PathBuf>, PathBuf, Option<PathBuf>, bool),
+    Train(TrainArgs),
+    Check(PathBuf, bool),
+    Help(String),
+    Repl()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the exit code and message of a failed run
+    fn cli(args:&[&str]) -> Result<(), (i32, String)> {
+        let args = ["rust_tokenizer"].iter().chain(args).map(|arg| arg.to_string()).collect();
+        run(args).map_err(|e| (e.exit_code(), e.to_string()))
+    }
+
+    #[test]
+    fn models_saved_as_rank_files_encode_the_same() {
+        let dir = env::temp_dir().join(format!("rust_tokenizer-cli-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name:&str| dir.join(name).to_str().unwrap().to_string();
+        fs::write(path("corpus.txt"), "the cat sat on the mat\nthe dog sat on the log\n".repeat(20)).unwrap();
+
+        for model in ["model.txt", "model.tiktoken"] {
+            cli(&["train", &path("corpus.txt"), "--vocab-size", "300", "-q", "-o", &path(model)]).unwrap();
+            cli(&["encode", &path("corpus.txt"), "-m", &path(model), "-o", &path(&format!("{}.ids", model))]).unwrap();
+        }
+        let ids = fs::read_to_string(path("model.txt.ids")).unwrap();
+        assert!(ids.split(',').any(|id| id.parse::<u32>().unwrap() >= 256));
+        assert_eq!(fs::read_to_string(path("model.tiktoken.ids")).unwrap(), ids);
+
+        // what a rank file can't keep is refused before training
+        for extra in [["--pattern", "gpt4"], ["--special", "<|end|>"], ["--meta", "a=b"]] {
+            let failed = cli(&["train", &path("corpus.txt"), "-q", "-o", &path("other.tiktoken"), extra[0], extra[1]]);
+            assert!(matches!(failed, Err((2, _))));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn repl_decodes_what_the_cli_encodes() {
+        let dir = env::temp_dir().join(format!("rust_tokenizer-repl-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name:&str| dir.join(name).to_str().unwrap().to_string();
+        let text = "the cat sat on the mat\nthe dog sat on the log\n".repeat(20);
+        fs::write(path("corpus.txt"), &text).unwrap();
+        cli(&["train", &path("corpus.txt"), "--vocab-size", "300", "-q", "-o", &path("model.txt")]).unwrap();
+        cli(&["encode", &path("corpus.txt"), "-m", &path("model.txt"), "-o", &path("ids.txt")]).unwrap();
+        // encode to stdout ends with a newline, like it would in a file from encode > ids.txt
+        let ids = fs::read_to_string(path("ids.txt")).unwrap();
+        fs::write(path("ids.txt"), ids + "\n").unwrap();
+
+        let model = tokenizer::load(Path::new(&path("model.txt"))).unwrap();
+        assert_eq!(repl_decode(Path::new(&path("ids.txt")), model.as_ref()).unwrap(), text);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use crate::basic::BasicTokenizer;
+use crate::error::TokenizerError;
+
+// first line of minbpe's .model files
+pub const VERSION: &str = "minbpe v1";
+
+// minbpe's .model format, one item per line:
+// the version
+// split pattern, empty for the basic tokenizer
+// number of special tokens, then each as "<token> <id>"
+// merges as "<id> <id>" in order, the first one is 256 and so on
+// the vocab isn't stored, it's built from the merges like minbpe does
+pub fn write_model(tokenizer:&BasicTokenizer, pattern:&str) -> Result<String, TokenizerError> {
+    let mut merges: Vec<(&(u32, u32), &u32)> = tokenizer.merges.iter().collect();
+    merges.sort_by_key(|(_, idx)| **idx);
+    if let Some((_, ((a, b), idx))) = merges.iter().enumerate().find(|(i, (_, idx))| **idx != 256 + *i as u32) {
+        return Err(TokenizerError::Invalid(format!("Merge ({},{}) has id {}, minbpe's format needs merge ids to count up from 256", a, b, idx)));
+    }
+    let mut specials: Vec<(&String, &u32)> = tokenizer.special_tokens.iter().collect();
+    specials.sort_by_key(|(_, idx)| **idx);
+    if let Some((special, _)) = specials.iter().find(|(special, _)| special.contains(char::is_whitespace)) {
+        return Err(TokenizerError::Invalid(format!("Special token {:?} has whitespace in it, minbpe's format can't hold it", special)));
+    }
+
+    let mut model = format!("{}\n{}\n{}\n", VERSION, pattern, specials.len());
+    for (special, idx) in specials {
+        model.push_str(&format!("{} {}\n", special, idx));
+    }
+    for ((a, b), _) in merges {
+        model.push_str(&format!("{} {}\n", a, b));
+    }
+    Ok(model)
+}
+
+// returns the tokenizer and its split pattern, lines are stripped like minbpe does
+pub fn parse_model(text:&str) -> Result<(BasicTokenizer, String), TokenizerError> {
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    let mut at = 0;
+    let mut next = |field:&str| {
+        at += 1;
+        lines.get(at - 1).map(|line| (at, *line)).ok_or_else(|| TokenizerError::parse(at, field, "missing, the file ends before it"))
+    };
+
+    let (n, version) = next("version")?;
+    if version != VERSION {
+        return Err(TokenizerError::parse(n, "version", format!("expected {:?}, got {:?}", VERSION, version)));
+    }
+    let (_, pattern) = next("split pattern")?;
+    let (n, count) = next("number of special tokens")?;
+    let count = count.parse::<usize>().map_err(|e| TokenizerError::parse(n, "number of special tokens", format!("{:?}, {}", count, e)))?;
+    let mut special_tokens = HashMap::new();
+    for _ in 0..count {
+        let (n, line) = next("special token")?;
+        match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [special, idx] => {
+                let idx = idx.parse::<u32>().map_err(|e| TokenizerError::parse(n, "special token", format!("{:?}, {}", idx, e)))?;
+                special_tokens.insert(special.to_string(), idx);
+            },
+            _ => return Err(TokenizerError::parse(n, "special token", format!("expected \"<token> <id>\", got {:?}", line)))
+        }
+    }
+
+    let merges_from = 3 + count;
+    let mut tokenizer = BasicTokenizer::untrained();
+    for (n, line) in lines.iter().enumerate().skip(merges_from).map(|(i, line)| (i + 1, line)) {
+        let pair: Option<Vec<u32>> = line.split_whitespace().map(|id| id.parse::<u32>().ok()).collect();
+        let idx = 256 + tokenizer.merges.len() as u32;
+        match pair.as_deref() {
+            Some([a, b]) if *a < idx && *b < idx => {
+                let bytes = [tokenizer.vocab[a].as_slice(), tokenizer.vocab[b].as_slice()].concat();
+                tokenizer.merges.insert((*a, *b), idx);
+                tokenizer.vocab.insert(idx, bytes);
+            },
+            Some([_, _]) => return Err(TokenizerError::parse(n, "merge", format!("{:?} uses an id that isn't below its own id {}", line, idx))),
+            _ => return Err(TokenizerError::parse(n, "merge", format!("expected \"<id> <id>\", got {:?}", line)))
+        }
+    }
+    tokenizer.trained = true;
+    tokenizer.num_merges = tokenizer.merges.len() as u32;
+    tokenizer.vocab_size = 256 + tokenizer.num_merges;
+    tokenizer.register_special_tokens(special_tokens)?;
+    Ok((tokenizer, pattern.to_string()))
+}
+
+// minbpe's .vocab file, for reading only: every token rendered as text, merged ones with the two
+// tokens they were merged from. special tokens come last like in minbpe's vocab
+pub fn write_vocab(tokenizer:&BasicTokenizer) -> String {
+    let parents: HashMap<u32, (u32, u32)> = tokenizer.merges.iter().map(|(pair, idx)| (*idx, *pair)).collect();
+    let mut ids: Vec<&u32> = tokenizer.vocab.keys().collect();
+    ids.sort();
+    let mut out = String::new();
+    for idx in ids {
+        let token = render_token(&tokenizer.vocab[idx]);
+        match parents.get(idx) {
+            Some((a, b)) => out.push_str(&format!("[{}][{}] -> [{}] {}\n", render_token(&tokenizer.vocab[a]), render_token(&tokenizer.vocab[b]), token, idx)),
+            None => out.push_str(&format!("[{}] {}\n", token, idx))
+        }
+    }
+    let mut specials: Vec<(&String, &u32)> = tokenizer.special_tokens.iter().collect();
+    specials.sort_by_key(|(_, idx)| **idx);
+    for (special, idx) in specials {
+        out.push_str(&format!("[{}] {}\n", render_token(special.as_bytes()), idx));
+    }
+    out
+}
+
+// like minbpe's render_token: invalid utf-8 becomes U+FFFD and control characters are escaped as
+// \uXXXX. python escapes all of unicode's C categories, here it's control, format and private use
+// characters, unassigned code points are left as they are
+fn render_token(token:&[u8]) -> String {
+    String::from_utf8_lossy(token).chars().map(|ch| {
+        if is_other(ch) { format!("\\u{:04x}", ch as u32) } else { ch.to_string() }
+    }).collect()
+}
+
+fn is_other(ch:char) -> bool {
+    const FORMAT: [(u32, u32); 21] = [
+        (0xad, 0xad), (0x600, 0x605), (0x61c, 0x61c), (0x6dd, 0x6dd), (0x70f, 0x70f), (0x890, 0x891),
+        (0x8e2, 0x8e2), (0x180e, 0x180e), (0x200b, 0x200f), (0x202a, 0x202e), (0x2060, 0x2064),
+        (0x2066, 0x206f), (0xfeff, 0xfeff), (0xfff9, 0xfffb), (0x110bd, 0x110bd), (0x110cd, 0x110cd),
+        (0x13430, 0x1343f), (0x1bca0, 0x1bca3), (0x1d173, 0x1d17a), (0xe0001, 0xe0001), (0xe0020, 0xe007f)
+    ];
+    let c = ch as u32;
+    ch.is_control()
+        || FORMAT.iter().any(|(start, end)| (*start..=*end).contains(&c))
+        || (0xe000..=0xf8ff).contains(&c) || (0xf0000..=0xffffd).contains(&c) || (0x100000..=0x10fffd).contains(&c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_and_writes_minbpe_files() {
+        // as minbpe's RegexTokenizer saves it, merges "h"+"e", "l"+"l", "he"+"ll"
+        let model = "minbpe v1\n\\s+|\\w+\n1\n<|endoftext|> 259\n104 101\n108 108\n256 257\n";
+        let (tokenizer, pattern) = parse_model(model).unwrap();
+        assert_eq!(pattern, "\\s+|\\w+");
+        assert_eq!(tokenizer.vocab[&258], b"hell");
+        assert_eq!(tokenizer.encode("hello"), vec![258, 111]);
+        assert_eq!(write_model(&tokenizer, &pattern).unwrap(), model);
+
+        let vocab = write_vocab(&tokenizer);
+        assert!(vocab.starts_with("[\\u0000] 0\n"));
+        assert!(vocab.contains("[\\u000a] 10\n[\\u000b] 11\n"));
+        assert!(vocab.contains("[\u{fffd}] 200\n"));
+        assert!(vocab.ends_with("[he][ll] -> [hell] 258\n[<|endoftext|>] 259\n"));
+
+        assert!(matches!(parse_model("minbpe v1\n\n0\n104 300\n"), Err(TokenizerError::Parse{ line: 4, .. })));
+        assert!(matches!(parse_model("minbpe v2\n"), Err(TokenizerError::Parse{ line: 1, .. })));
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::basic::BasicTokenizer;
+use crate::corpus::Fingerprint;
+use crate::error::TokenizerError;
+use crate::train::TrainParams;
+
+// first word of our model files, the format version follows it
+pub const MAGIC: &str = "rust_tokenizer";
+// files without the magic line are version 1, they're still read and saved as the current version
+pub const VERSION: u32 = 2;
+
+// one field per line, its name then a space and the value:
+// rust_tokenizer 2
+// kind basic|regex
+// pattern <split pattern>, regex tokenizers only
+// specials <id,bytes.. ...>, the id then the bytes of each special token
+// params vocab_size=N min_frequency=M, if they're known
+// corpus <fingerprint>, if it's known
+// meta <key> <value>, any number of them
+// vocab_size <n>
+// num_merges <n>
+// merges <a,b,idx ...>
+// vocab <id,bytes.. ...>
+// everything is in id or key order, so saving the same model always gives the same bytes
+pub fn write_model(tokenizer:&BasicTokenizer, pattern:&str) -> String {
+    let mut model = String::new();
+    let mut field = |name:&str, value:&str| {
+        model.push_str(name);
+        if !value.is_empty() {
+            model.push(' ');
+            model.push_str(value);
+        }
+        model.push('\n');
+    };
+    field(MAGIC, &VERSION.to_string());
+    field("kind", if pattern.is_empty() { "basic" } else { "regex" });
+    if !pattern.is_empty() {
+        field("pattern", pattern);
+    }
+    let mut specials: Vec<(u32, &[u8])> = tokenizer.special_tokens.iter().map(|(special, idx)| (*idx, special.as_bytes())).collect();
+    specials.sort();
+    field("specials", &write_entries(specials));
+    if let Some(params) = &tokenizer.params {
+        field("params", &params.to_line());
+    }
+    if let Some(corpus) = &tokenizer.corpus {
+        field("corpus", &corpus.to_string());
+    }
+    for (key, value) in &tokenizer.metadata {
+        field("meta", &format!("{} {}", key, value));
+    }
+    field("vocab_size", &tokenizer.vocab_size.to_string());
+    field("num_merges", &tokenizer.num_merges.to_string());
+    let mut merges: Vec<(&(u32, u32), &u32)> = tokenizer.merges.iter().collect();
+    merges.sort_by_key(|(_, idx)| **idx);
+    field("merges", &merges.iter().map(|((a, b), idx)| format!("{},{},{}", a, b, idx)).collect::<Vec<_>>().join(" "));
+    let mut vocab: Vec<(u32, &[u8])> = tokenizer.vocab.iter().map(|(idx, bytes)| (*idx, bytes.as_slice())).collect();
+    vocab.sort();
+    field("vocab", &write_entries(vocab));
+    model
+}
+
+// "id,byte,byte,.." for each entry, separated by spaces
+fn write_entries(entries:Vec<(u32, &[u8])>) -> String {
+    entries.iter().map(|(idx, bytes)| {
+        let mut entry = idx.to_string();
+        for b in *bytes {
+            entry.push_str(&format!(",{}", b));
+        }
+        entry
+    }).collect::<Vec<_>>().join(" ")
+}
+
+// strict parsing stops at the first bad entry. lenient parsing skips it and goes on, keeping the
+// error as a warning, and rebuilds tokens it had to skip in the vocab from the merges
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    pub lenient: bool,
+    pub warnings: Vec<TokenizerError>
+}
+
+impl Diagnostics {
+    pub fn lenient() -> Self {
+        Diagnostics{ lenient: true, warnings: Vec::new() }
+    }
+
+    // the error when strict, a warning when lenient
+    fn skip(&mut self, e:TokenizerError) -> Result<(), TokenizerError> {
+        if !self.lenient {
+            return Err(e);
+        }
+        self.warnings.push(e);
+        Ok(())
+    }
+}
+
+// returns the tokenizer and its split pattern, which is empty for basic models. the version on the
+// first line has to be one this build knows, files without it are taken as version 1
+pub fn parse_model(text:&str) -> Result<(BasicTokenizer, String), TokenizerError> {
+    parse_model_with(text, &mut Diagnostics::default())
+}
+
+pub fn parse_model_with(text:&str, diagnostics:&mut Diagnostics) -> Result<(BasicTokenizer, String), TokenizerError> {
+    let first = text.lines().next().unwrap_or("");
+    match first.split_once(' ') {
+        Some((MAGIC, version)) => match version.parse::<u32>() {
+            Ok(VERSION) => parse_current(text, diagnostics),
+            Ok(newer) if newer > VERSION => Err(TokenizerError::parse(1, "version", format!("version {} is newer than this build reads, it reads up to {}", newer, VERSION))),
+            _ => Err(TokenizerError::parse(1, "version", format!("{:?} isn't a version this build reads, expected {}", version, VERSION)))
+        },
+        _ if first.parse::<u32>().is_ok() => parse_v1(text, diagnostics),
+        _ => Err(TokenizerError::parse(1, "header", format!("expected \"{} {}\", got {:?}, it isn't a model file", MAGIC, VERSION, first)))
+    }
+}
+
+// the value of a field, with its line and the column it starts at
+#[derive(Debug, Clone, Copy)]
+struct Value<'a> {
+    line: usize,
+    column: usize,
+    text: &'a str
+}
+
+impl<'a> Value<'a> {
+    // the space separated entries with the column each starts at
+    fn entries(self) -> impl Iterator<Item = (usize, &'a str)> {
+        let mut column = self.column;
+        self.text.split(' ').map(move |entry| {
+            let at = column;
+            column += entry.chars().count() + 1;
+            (at, entry)
+        }).filter(|(_, entry)| !entry.is_empty())
+    }
+
+    fn number(self, field:&str) -> Result<u32, TokenizerError> {
+        self.text.parse::<u32>().map_err(|e| TokenizerError::parse_at(self.line, self.column, field, format!("{:?}, {}", self.text, e)))
+    }
+}
+
+fn parse_current(text:&str, d:&mut Diagnostics) -> Result<(BasicTokenizer, String), TokenizerError> {
+    let mut fields: HashMap<&str, Value> = HashMap::new();
+    let mut metadata = BTreeMap::new();
+    let mut last = 1;
+    for (n, line) in text.lines().enumerate().skip(1).map(|(i, line)| (i + 1, line)) {
+        last = n;
+        let (name, value) = line.split_once(' ').unwrap_or((line, ""));
+        let value = Value{ line: n, column: name.chars().count() + 2, text: value };
+        match name {
+            "meta" => {
+                let (key, value) = value.text.split_once(' ').unwrap_or((value.text, ""));
+                if key.is_empty() {
+                    d.skip(TokenizerError::parse(n, "meta", "missing the key"))?;
+                } else if metadata.contains_key(key) {
+                    d.skip(TokenizerError::parse(n, "meta", format!("key {:?} is given twice", key)))?;
+                } else {
+                    metadata.insert(key.to_string(), value.to_string());
+                }
+            },
+            "kind" | "pattern" | "specials" | "params" | "corpus" | "vocab_size" | "num_merges" | "merges" | "vocab" => {
+                if fields.contains_key(name) {
+                    d.skip(TokenizerError::parse(n, name, "given twice, the first one is used"))?;
+                } else {
+                    fields.insert(name, value);
+                }
+            },
+            _ => d.skip(TokenizerError::parse(n, "field", format!("unknown field {:?}", name)))?
+        }
+    }
+    // a missing field most likely means the file was cut off
+    let field = |name:&str| fields.get(name).copied().ok_or_else(|| TokenizerError::parse(last + 1, name, "missing, the file ends before it"));
+    // the counts are only checked against the merges and vocab, lenient parsing can do without them
+    let mut count = |name:&str| -> Result<Option<u32>, TokenizerError> {
+        match field(name).and_then(|value| value.number(name)) {
+            Ok(count) => Ok(Some(count)),
+            Err(e) => d.skip(e).map(|_| None)
+        }
+    };
+    let kind = field("kind")?;
+    let pattern = fields.get("pattern").map_or("", |pattern| pattern.text);
+    match (kind.text, pattern.is_empty()) {
+        ("basic", true) | ("regex", false) => {},
+        ("basic", false) => return Err(TokenizerError::parse(kind.line, "kind", "basic tokenizers don't have a split pattern")),
+        ("regex", true) => return Err(TokenizerError::parse(kind.line, "kind", "regex tokenizers need a split pattern")),
+        _ => return Err(TokenizerError::parse(kind.line, "kind", format!("unknown tokenizer kind {:?}, expected basic or regex", kind.text)))
+    }
+    let vocab_size = count("vocab_size")?;
+    let num_merges = count("num_merges")?;
+    let mut tokenizer = build(vocab_size, num_merges, field("merges")?, field("vocab")?, d)?;
+    if let Some(value) = fields.get("params") {
+        match TrainParams::parse_line(value.text, value.line) {
+            Ok(params) => tokenizer.params = Some(params),
+            Err(e) => d.skip(e)?
+        }
+    }
+    if let Some(value) = fields.get("corpus") {
+        match u64::from_str_radix(value.text, 16) {
+            Ok(corpus) => tokenizer.corpus = Some(Fingerprint(corpus)),
+            Err(e) => d.skip(TokenizerError::parse_at(value.line, value.column, "corpus", format!("{:?}, {}", value.text, e)))?
+        }
+    }
+    tokenizer.metadata = metadata;
+    if let Some(specials) = fields.get("specials") {
+        register_specials(&mut tokenizer, *specials, d)?;
+    }
+    Ok((tokenizer, pattern.to_string()))
+}
+
+// version 1, a line each for the vocab size, number of merges, merges, vocab, split pattern,
+// special tokens and training params. files saved before regex support end after the vocab
+fn parse_v1(text:&str, d:&mut Diagnostics) -> Result<(BasicTokenizer, String), TokenizerError> {
+    let lines:Vec<&str> = text.split('\n').collect();
+    let line = |n:usize, field:&str| match lines.get(n - 1) {
+        Some(text) => Ok(Value{ line: n, column: 1, text }),
+        None => Err(TokenizerError::parse(n, field, "missing, the file ends before it"))
+    };
+    // the counts aren't checked against the rest in this version, validating the model does that
+    line(1, "vocab size")?.number("vocab size")?;
+    if let Err(e) = line(2, "number of merges").and_then(|value| value.number("number of merges")) {
+        d.skip(e)?;
+    }
+    let mut tokenizer = build(None, None, line(3, "merges")?, line(4, "vocab")?, d)?;
+    let pattern = lines.get(4).unwrap_or(&"").to_string();
+    if let Some(line) = lines.get(6).filter(|line| !line.is_empty()) {
+        match TrainParams::parse_line(line, 7) {
+            Ok(params) => tokenizer.params = Some(params),
+            Err(e) => d.skip(e)?
+        }
+    }
+    register_specials(&mut tokenizer, Value{ line: 6, column: 1, text: lines.get(5).unwrap_or(&"") }, d)?;
+    Ok((tokenizer, pattern))
+}
+
+// the merges and vocab both versions have, the counts have to match them
+fn build(vocab_size:Option<u32>, num_merges:Option<u32>, merges:Value, vocab:Value, d:&mut Diagnostics) -> Result<BasicTokenizer, TokenizerError> {
+    let mut tokenizer = BasicTokenizer::untrained();
+    tokenizer.merges = parse_merges(merges, d)?;
+    if let Some(num_merges) = num_merges.filter(|n| *n as usize != tokenizer.merges.len()) {
+        d.skip(TokenizerError::parse(merges.line, "merges", format!("has {} merges, num_merges is {}", tokenizer.merges.len(), num_merges)))?;
+    }
+    tokenizer.vocab = parse_vocab(vocab, d)?;
+    if d.lenient {
+        let mut merges: Vec<(&(u32, u32), &u32)> = tokenizer.merges.iter().collect();
+        merges.sort_by_key(|(_, idx)| **idx);
+        for ((a, b), idx) in merges {
+            if tokenizer.vocab.contains_key(idx) {
+                continue;
+            }
+            if let (Some(left), Some(right)) = (tokenizer.vocab.get(a), tokenizer.vocab.get(b)) {
+                let bytes = [left.as_slice(), right.as_slice()].concat();
+                tokenizer.vocab.insert(*idx, bytes);
+                d.warnings.push(TokenizerError::parse(vocab.line, "vocab", format!("id {} is missing, rebuilt from merge ({},{})", idx, a, b)));
+            }
+        }
+    }
+    if let Some(vocab_size) = vocab_size.filter(|n| *n as usize != tokenizer.vocab.len()) {
+        d.skip(TokenizerError::parse(vocab.line, "vocab", format!("has {} tokens, vocab_size is {}", tokenizer.vocab.len(), vocab_size)))?;
+    }
+    tokenizer.trained = true;
+    tokenizer.num_merges = tokenizer.merges.len() as u32;
+    tokenizer.vocab_size = tokenizer.vocab.len() as u32;
+    Ok(tokenizer)
+}
+
+// "a,b,idx" separated by spaces
+fn parse_merges(value:Value, d:&mut Diagnostics) -> Result<HashMap<(u32, u32), u32>, TokenizerError> {
+    let mut merges = HashMap::new();
+    for (column, merge) in value.entries() {
+        let parsed = numbers(merge, column).and_then(|numbers| match numbers.as_slice() {
+            [(_, a), (_, b), (_, idx)] if merges.contains_key(&(*a, *b)) => Err((column, format!("merge ({},{}) -> {} is given twice", a, b, idx))),
+            [(_, a), (_, b), (_, idx)] => Ok(((*a, *b), *idx)),
+            _ => Err((column, format!("{:?} isn't a,b,idx", merge)))
+        });
+        match parsed {
+            Ok((pair, idx)) => { merges.insert(pair, idx); },
+            Err((at, message)) => d.skip(TokenizerError::parse_at(value.line, at, "merges", message))?
+        }
+    }
+    Ok(merges)
+}
+
+// "id,byte,byte,.." separated by spaces
+fn parse_vocab(value:Value, d:&mut Diagnostics) -> Result<HashMap<u32, Vec<u8>>, TokenizerError> {
+    let mut vocab = HashMap::new();
+    for (column, entry) in value.entries() {
+        match id_and_bytes(entry, column) {
+            Ok((idx, _)) if vocab.contains_key(&idx) => d.skip(TokenizerError::parse_at(value.line, column, "vocab", format!("id {} is given twice", idx)))?,
+            Ok((idx, bytes)) => { vocab.insert(idx, bytes); },
+            Err((at, message)) => d.skip(TokenizerError::parse_at(value.line, at, "vocab", message))?
+        }
+    }
+    Ok(vocab)
+}
+
+// like the vocab, registered in id order
+fn register_specials(tokenizer:&mut BasicTokenizer, value:Value, d:&mut Diagnostics) -> Result<(), TokenizerError> {
+    let mut specials = Vec::new();
+    for (column, entry) in value.entries() {
+        let parsed = id_and_bytes(entry, column).and_then(|(idx, bytes)| match String::from_utf8(bytes) {
+            Ok(token) => Ok((column, idx, token)),
+            Err(_) => Err((column, format!("{:?} isn't valid utf-8", entry)))
+        });
+        match parsed {
+            Ok(special) => specials.push(special),
+            Err((at, message)) => d.skip(TokenizerError::parse_at(value.line, at, "special tokens", message))?
+        }
+    }
+    specials.sort_by_key(|(_, idx, _)| *idx);
+    for (column, idx, token) in specials {
+        if tokenizer.special_tokens.contains_key(&token) {
+            d.skip(TokenizerError::parse_at(value.line, column, "special tokens", format!("{:?} is given twice", token)))?;
+        } else if let Err(e) = tokenizer.register_special_tokens(HashMap::from([(token, idx)])) {
+            d.skip(TokenizerError::parse_at(value.line, column, "special tokens", e.to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+// the comma separated numbers of an entry with the column each starts at. errors are the column
+// of the offending number and what's wrong with it
+fn numbers(entry:&str, column:usize) -> Result<Vec<(usize, u32)>, (usize, String)> {
+    let mut at = column;
+    entry.split(',').map(|number| {
+        let start = at;
+        at += number.chars().count() + 1;
+        number.parse::<u32>().map(|n| (start, n)).map_err(|e| (start, format!("{:?} in {:?} isn't a number, {}", number, entry, e)))
+    }).collect()
+}
+
+// an id then the bytes of its token
+fn id_and_bytes(entry:&str, column:usize) -> Result<(u32, Vec<u8>), (usize, String)> {
+    let numbers = numbers(entry, column)?;
+    match numbers.split_first() {
+        Some(((_, idx), bytes)) if !bytes.is_empty() => {
+            let bytes = bytes.iter()
+                .map(|(at, b)| u8::try_from(*b).map_err(|_| (*at, format!("{} in {:?} isn't a byte", b, entry))))
+                .collect::<Result<_, _>>()?;
+            Ok((*idx, bytes))
+        },
+        _ => Err((column, format!("{:?} isn't an id followed by bytes", entry)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn headers_are_checked_and_old_models_migrate() {
+        let bytes: String = (0..=255).map(|b| format!("{},{} ", b, b)).collect();
+        let v1 = format!("258\n2\n97,98,256 256,99,257 \n{}256,97,98 257,97,98,99 \n\n258,60,101,62 \nvocab_size=258 min_frequency=1", bytes);
+        let (mut tokenizer, pattern) = parse_model(&v1).unwrap();
+        assert_eq!(tokenizer.encode("abc<e>"), vec![257, 60, 101, 62]);
+        tokenizer.corpus = Some(Fingerprint::of(b"abc"));
+        tokenizer.set_metadata("source", "a test, with spaces").unwrap();
+
+        // saved again it gets the current header and reads back the same
+        let v2 = write_model(&tokenizer, &pattern);
+        assert!(v2.starts_with("rust_tokenizer 2\nkind basic\nspecials 258,60,101,62\nparams vocab_size=258 min_frequency=1\n"));
+        assert!(v2.contains("\nmeta source a test, with spaces\n"));
+        let (loaded, _) = parse_model(&v2).unwrap();
+        assert_eq!(loaded.corpus, tokenizer.corpus);
+        assert_eq!(loaded.metadata, tokenizer.metadata);
+        assert_eq!(write_model(&loaded, ""), v2);
+
+        let cut = &v2[..v2.len() - 100];
+        let newer = v2.replacen("rust_tokenizer 2", "rust_tokenizer 3", 1);
+        let cases = [(cut, 10), (newer.as_str(), 1), ("tiktoken 2\n", 1), ("rust_tokenizer 2\nkind regex\n", 2)];
+        for (model, line) in cases {
+            match parse_model(model) {
+                Err(TokenizerError::Parse{ line: at, .. }) => assert_eq!(at, line, "{:?}", model),
+                other => panic!("{:?} gave {:?}", model, other.map(|_| ()))
+            }
+        }
+    }
+
+    #[test]
+    fn bad_entries_give_their_column_and_lenient_parsing_skips_them() {
+        let tokenizer = testing::basic(270);
+        let model = write_model(&tokenizer, "");
+        let (n, vocab) = model.lines().enumerate().find(|(_, line)| line.starts_with("vocab ")).unwrap();
+        let at = vocab.find(" 256,").unwrap();
+        let without_field = model.replacen(vocab, &vocab.replacen(" 256,", " 256,x,", 1), 1);
+        let broken = without_field.replacen("\nvocab_size", "\nfavourite 7\nvocab_size", 1);
+
+        match parse_model(&broken) {
+            Err(e @ TokenizerError::Parse{ .. }) => assert!(e.to_string().ends_with("field: unknown field \"favourite\""), "{}", e),
+            other => panic!("gave {:?}", other.map(|_| ()))
+        }
+        match parse_model(&without_field) {
+            Err(TokenizerError::Parse{ line, column, message, .. }) => {
+                assert_eq!((line, column), (n + 1, Some(at + 6)));
+                assert!(message.starts_with("\"x\" in \"256,x,"), "{}", message);
+                let e = TokenizerError::Parse{ line, column, field: "vocab".to_string(), message };
+                assert!(e.to_string().starts_with(&format!("Line {}, column {}, vocab: ", n + 1, at + 6)), "{}", e);
+            },
+            other => panic!("gave {:?}", other.map(|_| ()))
+        }
+
+        // the unknown field and the bad entry are skipped, the token is made again from its merge
+        let mut diagnostics = Diagnostics::lenient();
+        let (loaded, _) = parse_model_with(&broken, &mut diagnostics).unwrap();
+        assert_eq!(diagnostics.warnings.len(), 3, "{:?}", diagnostics.warnings);
+        assert!(diagnostics.warnings[2].to_string().contains("id 256 is missing, rebuilt from merge"));
+        assert_eq!(loaded.vocab, tokenizer.vocab);
+        assert!(loaded.validate().is_ok());
+    }
+}
@@ -0,0 +1,289 @@
+use core::fmt;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::path::Path;
+
+use fancy_regex::Regex;
+
+use crate::binary;
+use crate::error::TokenizerError;
+use crate::minbpe;
+use crate::model;
+use crate::corpus::{read_blocks, read_sources, ChunkCounter, Chunks, Fingerprint, Fingerprinted, Source};
+use crate::special::{split_special, AllowedSpecial, Segment};
+use crate::stream::Cut;
+use crate::tiktoken;
+use crate::tokenizer::Tokenizer;
+use crate::train::{count_chunks, TrainOptions};
+use crate::BasicTokenizer;
+
+// how much text is split into chunks at once when streaming
+const BLOCK_SIZE: usize = 4 << 20;
+
+// split patterns from minbpe/tiktoken
+pub const GPT2_SPLIT_PATTERN: &str = r"'(?:[sdmt]|ll|ve|re)| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+pub const GPT4_SPLIT_PATTERN: &str = r"'(?i:[sdmt]|ll|ve|re)|[^\r\n\p{L}\p{N}]?+\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]++[\r\n]*|\s*[\r\n]|\s+(?!\S)|\s+";
+
+// "gpt2"/"gpt4" select the presets, anything else is taken as the pattern itself
+pub fn split_pattern(name:&str) -> &str {
+    match name {
+        "gpt2" => GPT2_SPLIT_PATTERN,
+        "gpt4" => GPT4_SPLIT_PATTERN,
+        _ => name
+    }
+}
+
+// text is split into chunks by the pattern first, merges never cross chunk boundaries.
+// merges and vocab are kept in a BasicTokenizer so saving/loading goes through the same path
+#[derive(Debug)]
+pub struct RegexTokenizer {
+    pattern: String,
+    compiled: Regex,
+    pub(crate) inner: BasicTokenizer
+}
+
+impl RegexTokenizer {
+    pub fn train(text:&str, vocab_size:u32, verbose:Option<bool>, pattern:&str) -> Result<Self, TokenizerError> {
+        Self::train_with_options(text, vocab_size, verbose, pattern, &TrainOptions::default())
+    }
+
+    pub fn train_with_options(text:&str, vocab_size:u32, verbose:Option<bool>, pattern:&str, options:&TrainOptions) -> Result<Self, TokenizerError> {
+        let compiled = compile(pattern)?;
+
+        let pieces = split(&compiled, text)?;
+        let chunks = if options.dedup {
+            count_chunks(&pieces, options.threads)
+        } else {
+            pieces.into_iter().map(|chunk| (chunk.as_bytes().to_vec(), 1)).collect()
+        };
+
+        Self::train_chunks(Chunks::Bytes(chunks), compiled, vocab_size, verbose, pattern, options, Fingerprint::of(text.as_bytes()))
+    }
+
+    // streams the text in blocks instead of reading it all, chunks are always deduplicated and
+    // the chunk table spills to disk past options.memory_limit
+    pub fn train_from_reader(reader: impl Read, vocab_size:u32, verbose:Option<bool>, pattern:&str, options:&TrainOptions) -> Result<Self, TokenizerError> {
+        let compiled = compile(pattern)?;
+        let (chunks, corpus) = count_reader(reader, &compiled, &Cut::for_pattern(pattern), options)?;
+        Self::train_chunks(chunks, compiled, vocab_size, verbose, pattern, options, corpus)
+    }
+
+    // like train_from_reader over every document of the sources, merges don't cross from one document to the next
+    pub fn train_from_sources(sources:&[Source], vocab_size:u32, verbose:Option<bool>, pattern:&str, options:&TrainOptions) -> Result<Self, TokenizerError> {
+        let compiled = compile(pattern)?;
+        let (chunks, corpus) = count_sources(sources, &compiled, &Cut::for_pattern(pattern), verbose.unwrap_or(false), options)?;
+        Self::train_chunks(chunks, compiled, vocab_size, verbose, pattern, options, corpus)
+    }
+
+    fn train_chunks(chunks:Chunks, compiled:Regex, vocab_size:u32, verbose:Option<bool>, pattern:&str, options:&TrainOptions, corpus:Fingerprint) -> Result<Self, TokenizerError> {
+        BasicTokenizer::check_vocab_size(vocab_size)?;
+        let mut inner = BasicTokenizer::untrained();
+        inner.learn(chunks, vocab_size, verbose, options, corpus)?;
+        Ok(RegexTokenizer{ pattern: pattern.to_string(), compiled, inner })
+    }
+
+    // keeps every existing id and learns new merges on top of them until vocab_size, the text is
+    // split with this tokenizer's pattern
+    pub fn continue_from_reader(&mut self, reader: impl Read, vocab_size:u32, verbose:Option<bool>, options:&TrainOptions) -> Result<(), TokenizerError> {
+        self.inner.check_continue(vocab_size)?;
+        let (chunks, corpus) = count_reader(reader, &self.compiled, &Cut::for_pattern(&self.pattern), options)?;
+        self.inner.learn(chunks, vocab_size, verbose, options, corpus)
+    }
+
+    pub fn continue_from_sources(&mut self, sources:&[Source], vocab_size:u32, verbose:Option<bool>, options:&TrainOptions) -> Result<(), TokenizerError> {
+        self.inner.check_continue(vocab_size)?;
+        let (chunks, corpus) = count_sources(sources, &self.compiled, &Cut::for_pattern(&self.pattern), verbose.unwrap_or(false), options)?;
+        self.inner.learn(chunks, vocab_size, verbose, options, corpus)
+    }
+
+    // for tokenizers built from existing merges, like the GPT4Tokenizer
+    pub(crate) fn from_parts(pattern:&str, inner:BasicTokenizer) -> Result<Self, TokenizerError> {
+        Ok(RegexTokenizer{ pattern: pattern.to_string(), compiled: compile(pattern)?, inner })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn encode(&self, text:&str) -> Result<Vec<u32>, TokenizerError> {
+        self.encode_mapped(text, |b| b as u32)
+    }
+
+    // byte_id gives the starting id of each byte, the identity unless the byte tokens were permuted
+    pub(crate) fn encode_mapped(&self, text:&str, byte_id: impl Fn(u8) -> u32) -> Result<Vec<u32>, TokenizerError> {
+        let mut ids = Vec::with_capacity(text.len());
+        for chunk in split(&self.compiled, text)? {
+            ids.extend(self.inner.encode_ids(chunk.bytes().map(&byte_id).collect()));
+        }
+        Ok(ids)
+    }
+
+    pub fn encode_with_special(&self, text:&str, allowed:&AllowedSpecial) -> Result<Vec<u32>, TokenizerError> {
+        self.encode_with_special_mapped(text, allowed, |b| b as u32)
+    }
+
+    pub(crate) fn encode_with_special_mapped(&self, text:&str, allowed:&AllowedSpecial, byte_id: impl Fn(u8) -> u32) -> Result<Vec<u32>, TokenizerError> {
+        let mut ids = Vec::with_capacity(text.len());
+        for segment in split_special(text, &self.inner.special_tokens, allowed)? {
+            match segment {
+                Segment::Text(part) => ids.extend(self.encode_mapped(part, &byte_id)?),
+                Segment::Special(idx) => ids.push(idx)
+            }
+        }
+        Ok(ids)
+    }
+
+    pub fn register_special_tokens(&mut self, special_tokens:HashMap<String, u32>) -> Result<(), TokenizerError> {
+        self.inner.register_special_tokens(special_tokens)
+    }
+
+    pub fn decode(&self, ids: Vec<u32>) -> Result<String, TokenizerError> {
+        self.inner.decode(ids)
+    }
+
+    // the format by extension, see Format::from_path. .model is our text format, minbpe's is only
+    // written with save_as and Format::Minbpe
+    pub fn save(&self, path:&Path) -> Result<(), TokenizerError> {
+        Tokenizer::save(self, path)
+    }
+
+    pub fn save_str(&self) -> String {
+        model::write_model(&self.inner, &self.pattern)
+    }
+
+    pub fn load(path:&Path) -> Result<Self, TokenizerError> {
+        let (inner, pattern) = BasicTokenizer::read_model(path)?;
+        inner.validate()?;
+        if pattern.is_empty() {
+            return Err(TokenizerError::Invalid(format!("Model at {} has no split pattern, it's a BasicTokenizer model", path.to_str().unwrap_or("?"))));
+        }
+        Ok(RegexTokenizer{ compiled: compile(&pattern)?, pattern, inner })
+    }
+}
+
+pub fn compile(pattern:&str) -> Result<Regex, TokenizerError> {
+    Regex::new(pattern).map_err(|e| TokenizerError::Pattern(format!("Invalid split pattern {:?}: {}", pattern, e)))
+}
+
+// the chunks and the fingerprint of what was read
+fn count_reader(reader: impl Read, compiled:&Regex, cut:&Cut, options:&TrainOptions) -> Result<(Chunks, Fingerprint), TokenizerError> {
+    let mut counter = ChunkCounter::new(options.memory_limit, options.spill_dir.as_deref());
+    let mut fingerprint = Fingerprint::default();
+    count_chunks_from(Fingerprinted{ reader, fingerprint: &mut fingerprint }, compiled, cut, options.memory_limit, &mut counter)?;
+    Ok((Chunks::Counted(counter), fingerprint))
+}
+
+fn count_sources(sources:&[Source], compiled:&Regex, cut:&Cut, verbose:bool, options:&TrainOptions) -> Result<(Chunks, Fingerprint), TokenizerError> {
+    let mut counter = ChunkCounter::new(options.memory_limit, options.spill_dir.as_deref());
+    let fingerprint = read_sources(sources, verbose, |_, file| count_chunks_from(file, compiled, cut, options.memory_limit, &mut counter))?;
+    Ok((Chunks::Counted(counter), fingerprint))
+}
+
+// a block that can't be cut may take up to memory_limit bytes on top of the chunk table
+fn count_chunks_from(reader: impl Read, compiled:&Regex, cut:&Cut, memory_limit:usize, counter:&mut ChunkCounter) -> Result<u64, TokenizerError> {
+    read_blocks(reader, BLOCK_SIZE, memory_limit, cut, |block| {
+        for chunk in split(compiled, block)? {
+            counter.add(chunk.as_bytes())?;
+        }
+        Ok(())
+    })
+}
+
+fn split<'a>(compiled:&Regex, text:&'a str) -> Result<Vec<&'a str>, TokenizerError> {
+    compiled.find_iter(text)
+        .map(|m| m.map(|m| m.as_str()).map_err(|e| TokenizerError::Pattern(format!("Failed splitting text with the pattern: {}", e))))
+        .collect()
+}
+
+impl fmt::Display for RegexTokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "RegexTokenizer:\n\t   pattern: {}", self.pattern)?;
+        write!(f, "{}", self.inner)
+    }
+}
+
+impl Tokenizer for RegexTokenizer {
+    fn encode_with_special(&self, text:&str, allowed:&AllowedSpecial) -> Result<Vec<u32>, TokenizerError> {
+        self.encode_with_special(text, allowed)
+    }
+
+    fn decode_bytes(&self, ids:&[u32]) -> Result<Vec<u8>, TokenizerError> {
+        self.inner.decode_bytes(ids)
+    }
+
+    fn vocab_size(&self) -> u32 {
+        self.inner.vocab_size
+    }
+
+    fn id_to_bytes(&self, id:u32) -> Option<&[u8]> {
+        self.inner.id_to_bytes(id)
+    }
+
+    fn bytes_to_id(&self, bytes:&[u8]) -> Option<u32> {
+        self.inner.bytes_to_id(bytes)
+    }
+
+    fn special_tokens(&self) -> &HashMap<String, u32> {
+        &self.inner.special_tokens
+    }
+
+    fn add_special_tokens(&mut self, tokens:Vec<String>) -> Result<(), TokenizerError> {
+        self.inner.register_next_special_tokens(tokens)
+    }
+
+    fn metadata(&self) -> &BTreeMap<String, String> {
+        self.inner.metadata()
+    }
+
+    fn set_metadata(&mut self, key:&str, value:&str) -> Result<(), TokenizerError> {
+        self.inner.set_metadata(key, value)
+    }
+
+    fn problems(&self) -> Vec<String> {
+        self.inner.problems()
+    }
+
+    fn cut(&self) -> Cut {
+        Cut::for_pattern(&self.pattern)
+    }
+
+    fn continue_from_sources(&mut self, sources:&[Source], vocab_size:u32, verbose:Option<bool>, options:&TrainOptions) -> Result<(), TokenizerError> {
+        self.continue_from_sources(sources, vocab_size, verbose, options)
+    }
+
+    fn save_str(&self) -> Result<String, TokenizerError> {
+        Ok(self.save_str())
+    }
+
+    fn to_minbpe(&self) -> Result<(String, String), TokenizerError> {
+        Ok((minbpe::write_model(&self.inner, &self.pattern)?, minbpe::write_vocab(&self.inner)))
+    }
+
+    fn to_binary(&self) -> Result<Vec<u8>, TokenizerError> {
+        binary::write_model(&self.inner, &self.pattern)
+    }
+
+    fn to_tiktoken(&self) -> Result<String, TokenizerError> {
+        tiktoken::export_model(&self.inner, &self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_never_cross_chunks() {
+        let text = "ab.cd!ab.cd!ab?\n".repeat(20);
+        let chunks = split(&compile(GPT4_SPLIT_PATTERN).unwrap(), &text).unwrap();
+        let in_a_chunk = |token:&Vec<u8>| chunks.iter().any(|chunk| chunk.as_bytes().windows(token.len()).any(|bytes| bytes == token));
+        let learned = |tokenizer:&BasicTokenizer| tokenizer.vocab.iter().filter(|(id, _)| **id >= 256).map(|(_, bytes)| bytes.clone()).collect::<Vec<_>>();
+
+        let regex = RegexTokenizer::train(&text, 300, None, GPT4_SPLIT_PATTERN).unwrap();
+        assert!(!learned(&regex.inner).is_empty());
+        assert!(learned(&regex.inner).iter().all(in_a_chunk), "{:?}", learned(&regex.inner));
+        // the same text without a split does get merges across
+        let basic = BasicTokenizer::train(&text, 300, None).unwrap();
+        assert!(!learned(&basic).iter().all(in_a_chunk));
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::error::TokenizerError;
+
+// what encode does with special token strings found in the text, same options as minbpe
+#[derive(Debug, Clone)]
+pub enum AllowedSpecial {
+    // every registered special token becomes its id
+    All,
+    // special strings are encoded like ordinary text
+    None,
+    // like None but fails if the text contains any special string
+    NoneRaise,
+    // only these become their id, the rest are encoded like ordinary text
+    Set(HashSet<String>)
+}
+
+impl AllowedSpecial {
+    // all, none, raise or the allowed tokens separated by commas
+    pub fn from_arg(arg:&str) -> Self {
+        match arg {
+            "all" => AllowedSpecial::All,
+            "none" => AllowedSpecial::None,
+            "raise" => AllowedSpecial::NoneRaise,
+            tokens => AllowedSpecial::Set(tokens.split(',').filter(|token| !token.is_empty()).map(str::to_string).collect())
+        }
+    }
+}
+
+pub enum Segment<'a> {
+    Text(&'a str),
+    Special(u32)
+}
+
+// cuts text around the allowed special tokens, ordinary text in between is left for the tokenizer
+pub fn split_special<'a>(text:&'a str, special_tokens:&HashMap<String, u32>, allowed:&AllowedSpecial) -> Result<Vec<Segment<'a>>, TokenizerError> {
+    let specials: Vec<(&str, u32)> = match allowed {
+        AllowedSpecial::All => special_tokens.iter().map(|(s, idx)| (s.as_str(), *idx)).collect(),
+        AllowedSpecial::Set(set) => special_tokens.iter().filter(|(s, _)| set.contains(*s)).map(|(s, idx)| (s.as_str(), *idx)).collect(),
+        AllowedSpecial::None => Vec::new(),
+        AllowedSpecial::NoneRaise => {
+            if let Some(found) = special_tokens.keys().find(|s| text.contains(s.as_str())) {
+                return Err(TokenizerError::SpecialNotAllowed(found.clone()));
+            }
+            Vec::new()
+        }
+    };
+
+    let mut segments = Vec::new();
+    let mut rest = text;
+    loop {
+        // leftmost match, the longest one if several start at the same place
+        let next = specials.iter()
+            .filter_map(|(s, idx)| rest.find(s).map(|at| (at, usize::MAX - s.len(), *s, *idx)))
+            .min();
+        match next {
+            Some((at, _, special, idx)) => {
+                if at > 0 {
+                    segments.push(Segment::Text(&rest[..at]));
+                }
+                segments.push(Segment::Special(idx));
+                rest = &rest[at + special.len()..];
+            },
+            None => break
+        }
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BasicTokenizer;
+
+    #[test]
+    fn only_allowed_specials_become_their_id() {
+        let mut tokenizer = BasicTokenizer::untrained();
+        tokenizer.register_special_tokens(HashMap::from([("<|a|>".to_string(), 256), ("<|b|>".to_string(), 257)])).unwrap();
+        let text = "x<|a|>y<|b|>";
+        let plain = |text:&str| text.bytes().map(u32::from).collect::<Vec<u32>>();
+        let encode = |arg| tokenizer.encode_with_special(text, &AllowedSpecial::from_arg(arg));
+
+        assert_eq!(encode("all").unwrap(), [plain("x"), vec![256], plain("y"), vec![257]].concat());
+        assert_eq!(encode("none").unwrap(), plain(text));
+        assert!(matches!(encode("raise"), Err(TokenizerError::SpecialNotAllowed(_))));
+        assert_eq!(encode("<|b|>").unwrap(), [plain("x<|a|>y"), vec![257]].concat());
+        assert_eq!(encode("<|a|>,<|c|>").unwrap(), [plain("x"), vec![256], plain("y<|b|>")].concat());
+        // raise only cares about registered tokens
+        assert_eq!(tokenizer.encode_with_special("x<|c|>", &AllowedSpecial::NoneRaise).unwrap(), plain("x<|c|>"));
+    }
+}
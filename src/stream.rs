@@ -0,0 +1,288 @@
+use std::io::{Read, Write};
+
+use crate::error::TokenizerError;
+use crate::regex::{GPT2_SPLIT_PATTERN, GPT4_SPLIT_PATTERN};
+
+// how much more input is read before looking for a place to cut the text again
+const READ_SIZE: usize = 1 << 16;
+
+// where text can be cut so that encoding both sides gives the same ids as encoding all of it
+pub enum Cut {
+    // basic tokenizers merge across anything, but never between two bytes that no token has next
+    // to each other. indexed by first byte * 256 + second byte
+    Unjoined(Vec<bool>),
+    // the gpt2/gpt4 split patterns: before a whitespace char followed by a non-whitespace one, for
+    // gpt4 unless it's a line break. gpt4 can also be cut after a newline followed by non-whitespace
+    Gpt2,
+    Gpt4,
+    // other split patterns can match across anything, the text is only cut at the end
+    Never
+}
+
+impl Cut {
+    // the presets have cuts worked out for them, see Cut::Gpt2
+    pub fn for_pattern(pattern:&str) -> Self {
+        match pattern {
+            GPT2_SPLIT_PATTERN => Cut::Gpt2,
+            GPT4_SPLIT_PATTERN => Cut::Gpt4,
+            _ => Cut::Never
+        }
+    }
+
+    // the last place in text it can be cut, none if there is no safe one yet.
+    // only looks at cuts from `from` on, the ones before were checked by the previous call. a cut
+    // can depend on a few bytes after it, those close to `from` are looked at again
+    pub(crate) fn find(&self, text:&[u8], from:usize, specials:&[&[u8]]) -> Option<usize> {
+        let lookahead = 8 + specials.iter().map(|special| special.len()).max().unwrap_or(0);
+        (from.saturating_sub(lookahead).max(1)..text.len()).rev().find(|&i| {
+            // never inside a multibyte char or a special token
+            let boundary = !(0x80..0xc0).contains(&text[i]) && !specials.iter().any(|special| spans(text, i, special));
+            boundary && match self {
+                Cut::Unjoined(joined) => !joined[text[i - 1] as usize * 256 + text[i] as usize],
+                Cut::Gpt2 | Cut::Gpt4 => self.splits_at(text, i, specials),
+                Cut::Never => false
+            }
+        })
+    }
+
+    // whether the pattern's matches end at i however the text goes on, and the ones after start
+    // the same way without the text before. matches of both presets only take whitespace at their
+    // start, gpt4's punctuation and line break matches also take the line breaks after them
+    fn splits_at(&self, text:&[u8], i:usize, specials:&[&[u8]]) -> bool {
+        let Some((c, len)) = char_at(text, i) else { return false };
+        let gpt4 = matches!(self, Cut::Gpt4);
+        if gpt4 && text[i - 1] == b'\n' && !c.is_whitespace() {
+            return true;
+        }
+        if !c.is_whitespace() || (gpt4 && (c == '\r' || c == '\n')) {
+            return false;
+        }
+        // the whitespace run has to end in the same piece of text, a special token ends it too early
+        let rest = &text[i + len..];
+        let special = specials.iter().any(|special| special.starts_with(&rest[..rest.len().min(special.len())]));
+        !special && char_at(text, i + len).is_some_and(|(next, _)| !next.is_whitespace())
+    }
+}
+
+// the char starting at i, none if it isn't all there yet
+fn char_at(text:&[u8], i:usize) -> Option<(char, usize)> {
+    let len = match text.get(i)? {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        _ => 4
+    };
+    let c = std::str::from_utf8(text.get(i..i + len)?).ok()?.chars().next()?;
+    Some((c, len))
+}
+
+// whether special starts before i and ends after it, or could once more text is read
+fn spans(text:&[u8], i:usize, special:&[u8]) -> bool {
+    (i.saturating_sub(special.len() - 1)..i).any(|start| {
+        let end = (start + special.len()).min(text.len());
+        end > i && text[start..end] == special[..end - start]
+    })
+}
+
+// reads text until it can be cut safely, encodes what's before the cut and writes the ids comma
+// separated as they're ready, so the whole input is never in memory at once
+pub fn encode_stream(mut reader: impl Read, mut writer: impl Write, cut:&Cut, specials:&[&[u8]], mut encode: impl FnMut(&str) -> Result<Vec<u32>, TokenizerError>) -> Result<(), TokenizerError> {
+    let mut text: Vec<u8> = Vec::new();
+    let mut block = vec![0u8; READ_SIZE];
+    // bytes already encoded, only for error messages
+    let mut done = 0;
+    let mut first = true;
+    let mut write_ids = |ids:Vec<u32>, writer:&mut dyn Write| -> Result<(), TokenizerError> {
+        for id in ids {
+            let sep = if first { "" } else { "," };
+            first = false;
+            write!(writer, "{}{}", sep, id).map_err(TokenizerError::io("writing ids"))?;
+        }
+        Ok(())
+    };
+    loop {
+        let n = reader.read(&mut block).map_err(TokenizerError::io("reading input"))?;
+        if n == 0 {
+            break;
+        }
+        let checked = text.len();
+        text.extend_from_slice(&block[..n]);
+        if let Some(at) = cut.find(&text, checked, specials) {
+            let part = std::str::from_utf8(&text[..at]).map_err(|e| TokenizerError::InvalidUtf8(format!("Input isn't valid utf-8 after byte {}: {}", done, e)))?;
+            write_ids(encode(part)?, &mut writer)?;
+            text.drain(..at);
+            done += at;
+        }
+    }
+    let rest = std::str::from_utf8(&text).map_err(|e| TokenizerError::InvalidUtf8(format!("Input isn't valid utf-8 after byte {}: {}", done, e)))?;
+    write_ids(encode(rest)?, &mut writer)?;
+    writer.flush().map_err(TokenizerError::io("writing ids"))
+}
+
+// reads comma separated ids and writes the text they decode to as it goes. a char split between
+// two reads is held back until the rest of it arrives, invalid utf-8 becomes U+FFFD like decode does
+pub fn decode_stream(mut reader: impl Read, mut writer: impl Write, mut decode: impl FnMut(&[u32]) -> Result<Vec<u8>, TokenizerError>) -> Result<(), TokenizerError> {
+    let mut block = vec![0u8; READ_SIZE];
+    // digits of an id that may continue in the next read
+    let mut number: Vec<u8> = Vec::new();
+    let mut ids: Vec<u32> = Vec::new();
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut count = 0;
+    // ids can be spread over lines, errors say which one. an id is on the line its first digit is
+    let mut line = 1;
+    let mut number_line = 1;
+    loop {
+        let n = reader.read(&mut block).map_err(TokenizerError::io("reading input"))?;
+        for &b in &block[..n] {
+            if b == b',' {
+                ids.push(parse_id(&number, count, number_line)?);
+                count += 1;
+                number.clear();
+                number_line = line;
+            } else {
+                if !b.is_ascii_whitespace() && number.trim_ascii().is_empty() {
+                    number_line = line;
+                }
+                line += usize::from(b == b'\n');
+                number.push(b);
+            }
+        }
+        if n == 0 && !number.trim_ascii().is_empty() {
+            ids.push(parse_id(&number, count, number_line)?);
+        }
+        bytes.extend(decode(&ids)?);
+        ids.clear();
+        let valid = write_valid(&bytes, n == 0, &mut writer)?;
+        bytes.drain(..valid);
+        if n == 0 {
+            break;
+        }
+    }
+    writer.flush().map_err(TokenizerError::io("writing text"))
+}
+
+fn parse_id(number:&[u8], count:usize, line:usize) -> Result<u32, TokenizerError> {
+    let number = String::from_utf8_lossy(number);
+    number.trim().parse::<u32>().map_err(|e| TokenizerError::parse(line, format!("id number {}", count + 1), format!("{:?}, {}. Expected format is comma seperated numbers: 1,2,3,4,...", number, e)))
+}
+
+// writes the bytes as text up to a char that's cut off at the end, unless it's the end of the input.
+// returns how many bytes it took
+fn write_valid(mut bytes:&[u8], end:bool, writer:&mut impl Write) -> Result<usize, TokenizerError> {
+    let failed = TokenizerError::io("writing text");
+    let total = bytes.len();
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => {
+                writer.write_all(text.as_bytes()).map_err(&failed)?;
+                return Ok(total);
+            },
+            Err(e) => {
+                writer.write_all(&bytes[..e.valid_up_to()]).map_err(&failed)?;
+                match e.error_len() {
+                    Some(len) => {
+                        writer.write_all(char::REPLACEMENT_CHARACTER.to_string().as_bytes()).map_err(&failed)?;
+                        bytes = &bytes[e.valid_up_to() + len..];
+                    },
+                    // an incomplete char, the rest of it can still come
+                    None if !end => return Ok(total - bytes.len() + e.valid_up_to()),
+                    None => {
+                        writer.write_all(char::REPLACEMENT_CHARACTER.to_string().as_bytes()).map_err(&failed)?;
+                        return Ok(total);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::RegexTokenizer;
+    use crate::special::AllowedSpecial;
+    use crate::tokenizer::Tokenizer;
+    use crate::BasicTokenizer;
+    use std::collections::HashMap;
+
+    // hands out a few bytes per read so the text gets cut in lots of places
+    struct Trickle<'a>(&'a [u8], usize);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf:&mut [u8]) -> std::io::Result<usize> {
+            let n = self.1.min(self.0.len()).min(buf.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    fn ids(written:Vec<u8>) -> Vec<u32> {
+        String::from_utf8(written).unwrap().split(',').filter(|id| !id.is_empty()).map(|id| id.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn streaming_gives_the_same_ids_and_text() {
+        let text = "Hello world, hello there!\nünïcödé 日本語 text <|end|> with specials\n\n  spaces  and\ttabs\n".repeat(20);
+        let mut basic = BasicTokenizer::train(&text, 320, None).unwrap();
+        basic.register_special_tokens(HashMap::from([("<|end|>".to_string(), 320)])).unwrap();
+        let mut regex = RegexTokenizer::train(&text, 320, None, GPT4_SPLIT_PATTERN).unwrap();
+        regex.register_special_tokens(HashMap::from([("<|end|>".to_string(), 320)])).unwrap();
+        let specials: Vec<&[u8]> = vec![b"<|end|>"];
+
+        for size in [1, 3, 7, 64, 1 << 16] {
+            let mut out = Vec::new();
+            encode_stream(Trickle(text.as_bytes(), size), &mut out, &Cut::Unjoined(basic.joined_pairs()), &specials, |part| basic.encode_with_special(part, &AllowedSpecial::All)).unwrap();
+            let expected = basic.encode_with_special(&text, &AllowedSpecial::All).unwrap();
+            assert_eq!(ids(out), expected, "basic, reads of {}", size);
+
+            let mut out = Vec::new();
+            encode_stream(Trickle(text.as_bytes(), size), &mut out, &Cut::Gpt4, &specials, |part| regex.encode_with_special(part, &AllowedSpecial::All)).unwrap();
+            assert_eq!(ids(out), regex.encode_with_special(&text, &AllowedSpecial::All).unwrap(), "regex, reads of {}", size);
+
+            let encoded = expected.iter().map(u32::to_string).collect::<Vec<_>>().join(",") + "\n";
+            let mut out = Vec::new();
+            decode_stream(Trickle(encoded.as_bytes(), size), &mut out, |ids| basic.decode_bytes(ids)).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), text);
+        }
+
+        // a char cut off at the end is replaced like decode does
+        let mut out = Vec::new();
+        decode_stream(Trickle(b"230,151", 1), &mut out, |ids| basic.decode_bytes(ids)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), basic.decode(vec![230, 151]).unwrap());
+
+        // a bad id is on the line its digits are, not the one after it
+        for (input, line) in [("1,x\n", 1), ("1,\n2,x", 2), ("1,\n\n x\n,2", 3)] {
+            match decode_stream(Trickle(input.as_bytes(), 1), Vec::new(), |ids| basic.decode_bytes(ids)) {
+                Err(TokenizerError::Parse{ line: at, .. }) => assert_eq!(at, line, "{:?}", input),
+                other => panic!("{:?} gave {:?}", input, other)
+            }
+        }
+    }
+
+    #[test]
+    fn every_pattern_streams_like_the_whole_text() {
+        let text = [
+            "def f(x):\n    if x:\n\t\treturn x  \n\n    return 0\r\n",
+            "東京は日本の首都です。\n大阪は二番目です。\n",
+            "One line paragraph.\n\nAnother one, 12345 67!\n\n\n  indented  <|end|>  runs   of spaces\u{3000}wide\n"
+        ].concat().repeat(10);
+        let specials: Vec<&[u8]> = vec![b"<|end|>"];
+        for pattern in [GPT2_SPLIT_PATTERN, GPT4_SPLIT_PATTERN, "[^ ]+", r"\s*\S+"] {
+            let mut regex = RegexTokenizer::train(&text, 300, None, pattern).unwrap();
+            regex.register_special_tokens(HashMap::from([("<|end|>".to_string(), 300)])).unwrap();
+            let expected = regex.encode_with_special(&text, &AllowedSpecial::All).unwrap();
+            for size in [1, 2, 5, 64] {
+                let mut out = Vec::new();
+                encode_stream(Trickle(text.as_bytes(), size), &mut out, &regex.cut(), &specials, |part| regex.encode_with_special(part, &AllowedSpecial::All)).unwrap();
+                assert_eq!(ids(out), expected, "{:?}, reads of {}", pattern, size);
+            }
+        }
+        // the presets find cuts in text without ascii lines, so it isn't all read at once
+        for cut in [Cut::Gpt2, Cut::Gpt4] {
+            assert!(cut.find("東京は 日本の首都です".as_bytes(), 0, &[]).is_some());
+            assert!(cut.find(b"    indented\n\n    lines", 0, &[]).is_some());
+        }
+        assert_eq!(Cut::Never.find(text.as_bytes(), 0, &[]), None);
+    }
+}
@@ -0,0 +1,22 @@
+use std::collections::HashMap;
+
+use crate::regex::GPT4_SPLIT_PATTERN;
+use crate::{BasicTokenizer, RegexTokenizer};
+
+// a few words repeated, enough for a couple dozen merges
+pub fn text() -> String {
+    "hello world, hello there\nhello again, world\n".repeat(10)
+}
+
+pub fn basic(vocab_size:u32) -> BasicTokenizer {
+    BasicTokenizer::train(&text(), vocab_size, None).unwrap()
+}
+
+pub fn regex(vocab_size:u32) -> RegexTokenizer {
+    RegexTokenizer::train(&text(), vocab_size, None, GPT4_SPLIT_PATTERN).unwrap()
+}
+
+// byte tokens ranked in reverse, out of byte order like cl100k_base's
+pub fn reversed_bytes() -> HashMap<Vec<u8>, u32> {
+    (0..=255u8).map(|b| (vec![b], 255 - b as u32)).collect()
+}
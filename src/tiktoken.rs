@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::basic::BasicTokenizer;
+use crate::error::TokenizerError;
+use crate::gpt4::GPT4Tokenizer;
+use crate::regex::RegexTokenizer;
+use crate::tokenizer::Tokenizer;
+
+// tiktoken rank files have one "<base64 token bytes> <rank>" per line
+pub fn load_ranks(path:&Path) -> Result<HashMap<Vec<u8>, u32>, TokenizerError> {
+    let text = fs::read_to_string(path).map_err(TokenizerError::io(format!("reading tiktoken file at {}", path.to_str().unwrap_or("?"))))?;
+    parse_ranks(&text)
+}
+
+pub fn parse_ranks(text:&str) -> Result<HashMap<Vec<u8>, u32>, TokenizerError> {
+    let mut ranks = HashMap::new();
+    for (n, line) in text.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.split(' ');
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(token), Some(rank), None) => {
+                let token = base64_decode(token).ok_or_else(|| TokenizerError::parse(n+1, "token", format!("invalid base64 {}", token)))?;
+                let rank = rank.parse::<u32>().map_err(|e| TokenizerError::parse(n+1, "rank", format!("{}, {}", rank, e)))?;
+                ranks.insert(token, rank);
+            },
+            _ => return Err(TokenizerError::parse(n+1, "line", format!("expected \"<base64 token> <rank>\", got: {}", line)))
+        }
+    }
+    Ok(ranks)
+}
+
+// rank files don't say how text is split or what the special tokens are, so they're given here.
+// files with the byte tokens in byte order, like to_tiktoken writes, give a basic tokenizer or a regex
+// one if there's a pattern. others keep their ranks as ids with a byte shuffle, see GPT4Tokenizer
+pub fn load(path:&Path, pattern:&str, special_tokens:HashMap<String, u32>) -> Result<Box<dyn Tokenizer>, TokenizerError> {
+    import(load_ranks(path)?, pattern, special_tokens)
+}
+
+pub fn import(ranks:HashMap<Vec<u8>, u32>, pattern:&str, special_tokens:HashMap<String, u32>) -> Result<Box<dyn Tokenizer>, TokenizerError> {
+    if !in_byte_order(&ranks) {
+        if pattern.is_empty() {
+            return Err(TokenizerError::Invalid("Rank file's byte tokens aren't in byte order, it needs a split pattern".to_string()));
+        }
+        return Ok(Box::new(GPT4Tokenizer::from_ranks_with(ranks, pattern, special_tokens)?));
+    }
+    let merges = recover_merges(&ranks)?;
+    let vocab: HashMap<u32, Vec<u8>> = ranks.into_iter().map(|(token, rank)| (rank, token)).collect();
+    let mut tokenizer = BasicTokenizer{
+        trained: true,
+        vocab_size: vocab.len() as u32,
+        num_merges: merges.len() as u32,
+        merges, vocab,
+        ..BasicTokenizer::untrained()
+    };
+    tokenizer.register_special_tokens(special_tokens)?;
+    tokenizer.validate()?;
+    if pattern.is_empty() {
+        return Ok(Box::new(tokenizer));
+    }
+    Ok(Box::new(RegexTokenizer::from_parts(pattern, tokenizer)?))
+}
+
+// every byte token has the byte as its rank
+pub fn in_byte_order(ranks:&HashMap<Vec<u8>, u32>) -> bool {
+    (0..=255u8).all(|b| ranks.get([b].as_slice()) == Some(&u32::from(b)))
+}
+
+// export for a model load has to give back as it was. a rank file has no room for special tokens or
+// metadata, and without its split pattern a regex tokenizer would load as a basic one
+pub(crate) fn export_model(tokenizer:&BasicTokenizer, pattern:&str) -> Result<String, TokenizerError> {
+    let lost = if !pattern.is_empty() {
+        "the split pattern"
+    } else if !tokenizer.special_tokens.is_empty() {
+        "special tokens"
+    } else if !tokenizer.metadata.is_empty() {
+        "metadata"
+    } else {
+        return export(tokenizer);
+    };
+    Err(TokenizerError::Invalid(format!("A rank file can't keep {}, loading it would give a different tokenizer", lost)))
+}
+
+// the rank file of a model, its ids as the ranks. tiktoken merges whichever pair of tokens makes the
+// lowest ranked token, so every token needs bytes of its own and has to split back into its merge
+pub fn export(tokenizer:&BasicTokenizer) -> Result<String, TokenizerError> {
+    let mut ids: Vec<(&u32, &Vec<u8>)> = tokenizer.vocab.iter().collect();
+    ids.sort();
+    let mut ranks: HashMap<Vec<u8>, u32> = HashMap::new();
+    for (id, bytes) in ids {
+        if let Some(other) = ranks.insert(bytes.clone(), *id) {
+            return Err(TokenizerError::Invalid(format!("Ids {} and {} are both {:?}, a rank file can't tell them apart", other, id, String::from_utf8_lossy(bytes))));
+        }
+    }
+    let recovered = recover_merges(&ranks)?;
+    let mut merges: Vec<(&(u32, u32), &u32)> = tokenizer.merges.iter().collect();
+    merges.sort_by_key(|(_, idx)| **idx);
+    for ((a, b), idx) in merges {
+        if recovered.get(&(*a, *b)) != Some(idx) {
+            return Err(TokenizerError::Invalid(format!("Merge ({},{}) -> {} isn't the one tiktoken finds for its token, a rank file would encode differently", a, b, idx)));
+        }
+    }
+    Ok(write_ranks(&tokenizer.vocab))
+}
+
+// the rank file text for tokens given as rank -> bytes, in rank order
+pub fn write_ranks(tokens:&HashMap<u32, Vec<u8>>) -> String {
+    let mut ranks: Vec<(&u32, &Vec<u8>)> = tokens.iter().collect();
+    ranks.sort();
+    ranks.iter().map(|(rank, token)| format!("{} {}\n", base64_encode(token), rank)).collect()
+}
+
+// tiktoken doesn't store merges, the pair that made a token is found by running bpe over its bytes
+// with only the merges ranked before it allowed, the two parts left over are the pair.
+pub fn recover_merges(ranks:&HashMap<Vec<u8>, u32>) -> Result<HashMap<(u32, u32), u32>, TokenizerError> {
+    let mut merges = HashMap::new();
+    for (token, rank) in ranks {
+        if token.len() == 1 {
+            continue;
+        }
+        let parts = bpe(ranks, token, *rank);
+        // single bytes can be missing from the file too
+        match parts.as_slice() {
+            [a, b] if ranks.contains_key(a) && ranks.contains_key(b) => { merges.insert((ranks[a], ranks[b]), *rank); },
+            _ => return Err(TokenizerError::Invalid(format!("Token with rank {} doesn't split into a pair of lower ranked tokens: {:?}", rank, parts)))
+        }
+    }
+    Ok(merges)
+}
+
+fn bpe(ranks:&HashMap<Vec<u8>, u32>, token:&[u8], max_rank:u32) -> Vec<Vec<u8>> {
+    let mut parts: Vec<Vec<u8>> = token.iter().map(|b| vec![*b]).collect();
+    loop {
+        let lowest = parts.iter().zip(parts.iter().skip(1)).enumerate()
+            .filter_map(|(i, (a, b))| ranks.get(&[a.as_slice(), b.as_slice()].concat()).map(|rank| (*rank, i)))
+            .min();
+        match lowest {
+            Some((rank, i)) if rank < max_rank => {
+                let right = parts.remove(i + 1);
+                parts[i].extend(right);
+            },
+            _ => break
+        }
+    }
+    parts
+}
+
+fn base64_decode(text:&str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let mut buf: u32 = 0;
+    let mut bits = 0;
+    for ch in text.trim_end_matches('=').bytes() {
+        let val = match ch {
+            b'A'..=b'Z' => ch - b'A',
+            b'a'..=b'z' => ch - b'a' + 26,
+            b'0'..=b'9' => ch - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None
+        };
+        buf = (buf << 6) | val as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buf >> bits) as u8);
+            buf &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes:&[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let buf = group.iter().enumerate().fold(0u32, |buf, (i, b)| buf | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= group.len() {
+                out.push(BASE64[(buf >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::GPT4_SPLIT_PATTERN;
+    use crate::testing;
+
+    #[test]
+    fn exported_models_import_with_the_same_ids() {
+        let text = testing::text();
+        let specials = HashMap::from([("<|end|>".to_string(), 300)]);
+        let basic = testing::basic(280);
+        let regex = testing::regex(280);
+        let exported: [(&dyn Tokenizer, &BasicTokenizer, &str); 2] = [(&basic, &basic, ""), (&regex, &regex.inner, GPT4_SPLIT_PATTERN)];
+        for (tokenizer, merges, pattern) in exported {
+            let ranks = parse_ranks(&export(merges).unwrap()).unwrap();
+            let imported = import(ranks.clone(), pattern, HashMap::new()).unwrap();
+            assert_eq!(imported.encode(&text).unwrap(), tokenizer.encode(&text).unwrap());
+            let with_specials = import(ranks, pattern, specials.clone()).unwrap();
+            assert_eq!(with_specials.encode_with_special("hello<|end|>", &crate::AllowedSpecial::All).unwrap().last(), Some(&300));
+            // only models a rank file holds all of are saved as one
+            assert_eq!(imported.to_tiktoken().is_ok(), pattern.is_empty());
+            assert_eq!(tokenizer.to_tiktoken().is_ok(), pattern.is_empty());
+            assert!(with_specials.to_tiktoken().is_err());
+        }
+        assert_eq!(basic.to_tiktoken().unwrap(), export(&basic).unwrap());
+
+        // shuffled byte tokens keep their ranks, which needs a pattern to split on
+        let mut shuffled = testing::reversed_bytes();
+        shuffled.insert(b"he".to_vec(), 256);
+        assert!(import(shuffled.clone(), "", HashMap::new()).is_err());
+        let imported = import(shuffled, GPT4_SPLIT_PATTERN, HashMap::new()).unwrap();
+        assert_eq!(imported.encode("hehe").unwrap(), vec![256, 256]);
+        assert_eq!(imported.encode("a").unwrap(), vec![255 - 97]);
+
+        // two ids with the same bytes can't be told apart by rank
+        let mut twice = basic;
+        twice.vocab.insert(280, twice.vocab[&279].clone());
+        twice.merges.insert((279, 0), 280);
+        assert!(export(&twice).is_err());
+    }
+}
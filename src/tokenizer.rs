@@ -0,0 +1,281 @@
+use core::fmt;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::basic::{invalid_if_any, BasicTokenizer};
+use crate::binary::{self, BinaryModel};
+use crate::corpus::Source;
+use crate::error::TokenizerError;
+use crate::gpt4::GPT4Tokenizer;
+use crate::minbpe;
+use crate::model::{self, Diagnostics};
+use crate::regex::RegexTokenizer;
+use crate::special::AllowedSpecial;
+use crate::stream::Cut;
+use crate::tiktoken::{self, in_byte_order, parse_ranks};
+use crate::train::TrainOptions;
+
+// what the cli/repl need from a tokenizer, so they work the same with any kind of it
+pub trait Tokenizer: fmt::Display + fmt::Debug {
+    fn encode_with_special(&self, text:&str, allowed:&AllowedSpecial) -> Result<Vec<u32>, TokenizerError>;
+
+    // special token strings are encoded like ordinary text
+    fn encode(&self, text:&str) -> Result<Vec<u32>, TokenizerError> {
+        self.encode_with_special(text, &AllowedSpecial::None)
+    }
+
+    fn decode_bytes(&self, ids:&[u32]) -> Result<Vec<u8>, TokenizerError>;
+
+    // a slice of ids can end in the middle of a multibyte char, that's replaced instead of failing
+    fn decode(&self, ids:&[u32]) -> Result<String, TokenizerError> {
+        Ok(String::from_utf8_lossy(&self.decode_bytes(ids)?).into_owned())
+    }
+
+    // the byte and merge tokens, special tokens come on top
+    fn vocab_size(&self) -> u32;
+
+    // the bytes of a token or special token
+    fn id_to_bytes(&self, id:u32) -> Option<&[u8]>;
+
+    // the token that is exactly these bytes, the lowest id if several are
+    fn bytes_to_id(&self, bytes:&[u8]) -> Option<u32>;
+
+    fn special_tokens(&self) -> &HashMap<String, u32>;
+
+    // registers the tokens with the next free ids in order
+    fn add_special_tokens(&mut self, tokens:Vec<String>) -> Result<(), TokenizerError>;
+
+    // free-form key value pairs saved with the model
+    fn metadata(&self) -> &BTreeMap<String, String>;
+
+    fn set_metadata(&mut self, key:&str, value:&str) -> Result<(), TokenizerError>;
+
+    // see BasicTokenizer::problems
+    fn problems(&self) -> Vec<String>;
+
+    fn validate(&self) -> Result<(), TokenizerError> {
+        invalid_if_any(self.problems())
+    }
+
+    // where encode_stream can cut text for this tokenizer
+    fn cut(&self) -> Cut;
+
+    // learns merges on top of the existing ones until vocab_size, every id stays the same
+    fn continue_from_sources(&mut self, sources:&[Source], vocab_size:u32, verbose:Option<bool>, options:&TrainOptions) -> Result<(), TokenizerError>;
+
+    // the text load reads back, an error for models it can't hold
+    fn save_str(&self) -> Result<String, TokenizerError>;
+
+    // minbpe's .model and .vocab files, for tokenizers python minbpe can load
+    fn to_minbpe(&self) -> Result<(String, String), TokenizerError> {
+        Err(TokenizerError::Invalid("This tokenizer can't be saved in minbpe's format".to_string()))
+    }
+
+    // see binary::write_model
+    fn to_binary(&self) -> Result<Vec<u8>, TokenizerError> {
+        Err(TokenizerError::Invalid("This tokenizer can't be saved in the binary format".to_string()))
+    }
+
+    // see tiktoken::export, an error for models with more in them than a rank file holds
+    fn to_tiktoken(&self) -> Result<String, TokenizerError> {
+        Err(TokenizerError::Invalid("This tokenizer can't be saved as a tiktoken rank file".to_string()))
+    }
+
+    // in the format the path's extension asks for
+    fn save(&self, path:&Path) -> Result<(), TokenizerError> {
+        self.save_as(path, Format::from_path(path))
+    }
+
+    // minbpe's format writes the .vocab next to the path
+    fn save_as(&self, path:&Path, format:Format) -> Result<(), TokenizerError> {
+        match format {
+            Format::Text => write(path, self.save_str()?.into_bytes()),
+            Format::Binary => write(path, self.to_binary()?),
+            Format::Tiktoken => write(path, self.to_tiktoken()?.into_bytes()),
+            Format::Minbpe => {
+                let (model, vocab) = self.to_minbpe()?;
+                write(path, model.into_bytes())?;
+                write(&path.with_extension("vocab"), vocab.into_bytes())
+            }
+        }
+    }
+}
+
+pub(crate) fn write(path:&Path, bytes:Vec<u8>) -> Result<(), TokenizerError> {
+    fs::write(path, bytes).map_err(TokenizerError::io(format!("writing model to {}", path.to_str().unwrap_or("?"))))
+}
+
+// how a model is saved, load tells them apart by their first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    // see model::write_model
+    Text,
+    // see binary::write_model
+    Binary,
+    // minbpe's .model and .vocab
+    Minbpe,
+    // a tiktoken rank file, see tiktoken::export
+    Tiktoken
+}
+
+impl Format {
+    // .bin is binary, .tiktoken a rank file and anything else text, .model too since it's what our
+    // own models have always been called. minbpe's format is only written when asked for by name
+    pub fn from_path(path:&Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("bin") => Format::Binary,
+            Some("tiktoken") => Format::Tiktoken,
+            _ => Format::Text
+        }
+    }
+
+    pub fn from_name(name:&str) -> Option<Self> {
+        match name {
+            "text" => Some(Format::Text),
+            "binary" => Some(Format::Binary),
+            "minbpe" => Some(Format::Minbpe),
+            "tiktoken" => Some(Format::Tiktoken),
+            _ => None
+        }
+    }
+}
+
+// models that don't validate are an error
+pub fn load(path:&Path) -> Result<Box<dyn Tokenizer>, TokenizerError> {
+    let tokenizer = load_unchecked(path)?;
+    tokenizer.validate()?;
+    Ok(tokenizer)
+}
+
+// for looking at what's wrong with a model, see Tokenizer::problems
+pub fn load_unchecked(path:&Path) -> Result<Box<dyn Tokenizer>, TokenizerError> {
+    let bytes = fs::read(path).map_err(TokenizerError::io(format!("reading model from {}", path.to_str().unwrap_or("?"))))?;
+    parse_unchecked(&bytes, &mut Diagnostics::default())
+}
+
+// like load_unchecked but entries of our text format that don't parse are skipped, and returned
+// as warnings. see model::Diagnostics
+pub fn load_lenient(path:&Path) -> Result<(Box<dyn Tokenizer>, Vec<TokenizerError>), TokenizerError> {
+    let bytes = fs::read(path).map_err(TokenizerError::io(format!("reading model from {}", path.to_str().unwrap_or("?"))))?;
+    let mut diagnostics = Diagnostics::lenient();
+    let tokenizer = parse_unchecked(&bytes, &mut diagnostics)?;
+    Ok((tokenizer, diagnostics.warnings))
+}
+
+// a binary model or any of the text formats parse takes
+pub fn parse_bytes(bytes:&[u8]) -> Result<Box<dyn Tokenizer>, TokenizerError> {
+    let tokenizer = parse_unchecked(bytes, &mut Diagnostics::default())?;
+    tokenizer.validate()?;
+    Ok(tokenizer)
+}
+
+fn parse_unchecked(bytes:&[u8], diagnostics:&mut Diagnostics) -> Result<Box<dyn Tokenizer>, TokenizerError> {
+    if binary::is_binary(bytes) {
+        return kind(BinaryModel::parse(bytes)?.to_tokenizer()?);
+    }
+    let text = std::str::from_utf8(bytes).map_err(|e| TokenizerError::InvalidUtf8(format!("Model isn't a binary model or valid utf-8: {}", e)))?;
+    parse_text(text, diagnostics)
+}
+
+// our model files start with our magic and version (or the vocab size before there was a header),
+// minbpe's with its version and tiktoken rank files with a token and its rank. models with a split
+// pattern are regex tokenizers, rank files are basic ones unless their bytes are shuffled like
+// cl100k_base's, then they're taken as cl100k_base
+pub fn parse(text:&str) -> Result<Box<dyn Tokenizer>, TokenizerError> {
+    let tokenizer = parse_text(text, &mut Diagnostics::default())?;
+    tokenizer.validate()?;
+    Ok(tokenizer)
+}
+
+fn parse_text(text:&str, diagnostics:&mut Diagnostics) -> Result<Box<dyn Tokenizer>, TokenizerError> {
+    let first = text.lines().next().unwrap_or("").trim();
+    if first != minbpe::VERSION && !first.starts_with(model::MAGIC) && first.contains(' ') {
+        // a rank file doesn't say how to split or what the special tokens are, to_tiktoken only
+        // writes models that need neither
+        let ranks = parse_ranks(text)?;
+        if in_byte_order(&ranks) {
+            return tiktoken::import(ranks, "", HashMap::new());
+        }
+        return Ok(Box::new(GPT4Tokenizer::from_ranks(ranks)?));
+    }
+    kind(BasicTokenizer::parse_any(text, diagnostics)?)
+}
+
+// models with a split pattern are regex tokenizers
+fn kind((tokenizer, pattern):(BasicTokenizer, String)) -> Result<Box<dyn Tokenizer>, TokenizerError> {
+    if pattern.is_empty() {
+        return Ok(Box::new(tokenizer));
+    }
+    Ok(Box::new(RegexTokenizer::from_parts(&pattern, tokenizer)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::GPT2_SPLIT_PATTERN;
+    use crate::testing::{self, text};
+
+    #[test]
+    fn saved_tokenizers_load_as_the_same_kind() {
+        let text = text();
+        let basic = testing::basic(280);
+        let regex = testing::regex(280);
+        // a rank file with the bytes ranked in reverse and the basic tokenizer's merges on top
+        let mut ranks = testing::reversed_bytes();
+        ranks.extend(basic.vocab().iter().filter(|(id, _)| **id >= 256).map(|(id, bytes)| (bytes.clone(), *id)));
+        let gpt4 = GPT4Tokenizer::from_ranks(ranks).unwrap();
+
+        let tokenizers: Vec<Box<dyn Tokenizer>> = vec![Box::new(basic), Box::new(regex), Box::new(gpt4)];
+        for tokenizer in tokenizers {
+            let loaded = parse(&tokenizer.save_str().unwrap()).unwrap();
+            assert_eq!(loaded.to_string().lines().next(), tokenizer.to_string().lines().next());
+            let ids = tokenizer.encode(&text).unwrap();
+            assert_eq!(loaded.encode(&text).unwrap(), ids);
+            assert_eq!(loaded.decode(&ids).unwrap(), text);
+            assert_eq!(loaded.bytes_to_id(b"hello"), tokenizer.bytes_to_id(b"hello"));
+            assert!(loaded.id_to_bytes(ids[0]).is_some_and(|bytes| bytes.starts_with(b"hello")));
+        }
+    }
+
+    #[test]
+    fn rank_files_load_as_what_saved_them() {
+        let text = text();
+        let basic = testing::basic(280);
+        let loaded = parse(&basic.to_tiktoken().unwrap()).unwrap();
+        assert!(loaded.to_string().starts_with("BasicTokenizer:"));
+        assert_eq!(loaded.encode(&text).unwrap(), basic.encode(&text));
+
+        // shuffled bytes are taken as cl100k_base, which can't get other special tokens or patterns
+        let mut gpt4 = GPT4Tokenizer::from_ranks(testing::reversed_bytes()).unwrap();
+        assert!(gpt4.add_special_tokens(vec!["<|a|>".to_string()]).is_err());
+        let loaded = parse(&gpt4.to_tiktoken().unwrap()).unwrap();
+        assert_eq!(loaded.special_tokens(), gpt4.special_tokens());
+        let gpt2 = GPT4Tokenizer::from_ranks_with(testing::reversed_bytes(), GPT2_SPLIT_PATTERN, HashMap::new()).unwrap();
+        assert!(gpt2.to_tiktoken().is_err());
+        assert!(gpt2.save_as(Path::new("never-written.model"), Format::Text).is_err());
+        assert!(Tokenizer::save_str(&gpt2).is_err());
+    }
+
+    #[test]
+    fn saving_a_loaded_model_gives_the_same_bytes() {
+        let mut basic = testing::basic(280);
+        basic.add_special_tokens(vec!["<|a|>".to_string(), "<|b|>".to_string(), "<|c|>".to_string()]).unwrap();
+        let mut regex = testing::regex(280);
+        regex.add_special_tokens(vec!["<|a|>".to_string(), "<|b|>".to_string()]).unwrap();
+        let gpt4 = GPT4Tokenizer::from_ranks(testing::reversed_bytes()).unwrap();
+
+        let tokenizers: Vec<Box<dyn Tokenizer>> = vec![Box::new(basic), Box::new(regex), Box::new(gpt4)];
+        for tokenizer in tokenizers {
+            let saved = tokenizer.save_str().unwrap();
+            assert_eq!(parse(&saved).unwrap().save_str().unwrap(), saved);
+            if let Ok((model, vocab)) = tokenizer.to_minbpe() {
+                let loaded = parse(&model).unwrap();
+                assert_eq!(loaded.to_minbpe().unwrap(), (model, vocab));
+            }
+        }
+        // .model is our own text format, minbpe's has to be asked for
+        assert_eq!(Format::from_path(Path::new("mo.model")), Format::Text);
+        assert_eq!(Format::from_name("minbpe"), Some(Format::Minbpe));
+    }
+}
@@ -0,0 +1,437 @@
+use std::collections::{BinaryHeap, HashMap};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::thread;
+
+use crate::corpus::Chunks;
+use crate::error::TokenizerError;
+
+// position has no neighbour on that side, it's the start/end of its chunk
+const NONE: usize = usize::MAX;
+// id of a position that got merged into the one on its left
+const DEAD: u32 = u32::MAX;
+
+// a merge picked by the trainer, count is how often the pair occurred when it got picked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Merge {
+    pub pair: (u32, u32),
+    pub idx: u32,
+    pub count: u64
+}
+
+#[derive(Debug, Clone)]
+pub struct TrainOptions {
+    // train on the unique chunks weighted by how often they occur instead of every chunk,
+    // gives the same merges with far less work since real text repeats the same words a lot
+    pub dedup: bool,
+    // worker threads for counting and applying merges, the merges don't depend on it
+    pub threads: usize,
+    // merges with fewer occurrences are applied on the calling thread whatever threads is,
+    // spawning would cost more than it saves
+    pub parallel_min: usize,
+    // bytes the chunk table may take while streaming a corpus before it's spilled to disk
+    pub memory_limit: usize,
+    // where spilled chunk tables go, the system temp dir if unset
+    pub spill_dir: Option<PathBuf>,
+    // training stops early once no pair occurs at least this often
+    pub min_frequency: u64
+}
+
+// what decides the merges of a training run besides the corpus, saved with the model so the run
+// can be repeated. the split pattern and special tokens are saved with the model anyway
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrainParams {
+    // the vocab size asked for, the model has fewer tokens if training stopped early
+    pub vocab_size: u32,
+    pub min_frequency: u64
+}
+
+impl TrainParams {
+    // "key=value" pairs separated by spaces, one line of the model file
+    pub fn to_line(&self) -> String {
+        format!("vocab_size={} min_frequency={}", self.vocab_size, self.min_frequency)
+    }
+
+    // line_no is where the line is in the model file, for the errors
+    pub fn parse_line(line:&str, line_no:usize) -> Result<Self, TokenizerError> {
+        let mut params = TrainParams{ vocab_size: 0, min_frequency: 1 };
+        for field in line.split(' ').filter(|field| !field.is_empty()) {
+            let (key, value) = field.split_once('=').ok_or_else(|| TokenizerError::parse(line_no, "training params", format!("expected key=value, got: {}", field)))?;
+            let invalid = |e: std::num::ParseIntError| TokenizerError::parse(line_no, key, format!("{}, {}", value, e));
+            match key {
+                "vocab_size" => params.vocab_size = value.parse().map_err(invalid)?,
+                "min_frequency" => params.min_frequency = value.parse().map_err(invalid)?,
+                _ => return Err(TokenizerError::parse(line_no, key, "unknown training param"))
+            }
+        }
+        Ok(params)
+    }
+}
+
+impl Default for TrainOptions {
+    fn default() -> Self {
+        TrainOptions{
+            dedup: true,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            parallel_min: 1 << 16,
+            memory_limit: 1 << 30,
+            spill_dir: None,
+            min_frequency: 1
+        }
+    }
+}
+
+// runs f on every part, each on its own thread unless there's only one part
+fn parallel<P: Send, R: Send>(parts:Vec<P>, f: impl Fn(P) -> R + Sync) -> Vec<R> {
+    if parts.len() <= 1 {
+        return parts.into_iter().map(f).collect();
+    }
+    thread::scope(|scope| {
+        let f = &f;
+        let handles: Vec<_> = parts.into_iter().map(|part| scope.spawn(move || f(part))).collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    })
+}
+
+// 0..len cut into at most threads ranges of about the same size
+fn ranges(len:usize, threads:usize) -> Vec<Range<usize>> {
+    let size = len.div_ceil(threads.max(1)).max(1);
+    (0..len).step_by(size).map(|start| start..(start + size).min(len)).collect()
+}
+
+// unique chunks with their number of occurrences
+pub fn count_chunks(chunks:&[&str], threads:usize) -> Vec<(Vec<u8>, u64)> {
+    let shards = parallel(ranges(chunks.len(), threads), |range| {
+        let mut counts: HashMap<&str, u64> = HashMap::new();
+        for chunk in &chunks[range] {
+            *counts.entry(*chunk).or_default() += 1;
+        }
+        counts
+    });
+    let mut counts: HashMap<&str, u64> = HashMap::new();
+    for shard in shards {
+        for (chunk, count) in shard {
+            *counts.entry(chunk).or_default() += count;
+        }
+    }
+    counts.into_iter().map(|(chunk, count)| (chunk.as_bytes().to_vec(), count)).collect()
+}
+
+// the chunks laid out one after another as a doubly linked list, so merging a pair only
+// touches the positions it occurs at instead of rebuilding every chunk.
+// every position carries the weight of its chunk
+pub(crate) struct Sequence {
+    ids: Vec<u32>,
+    prev: Vec<usize>,
+    next: Vec<usize>,
+    weight: Vec<u64>,
+    // first position of every chunk
+    starts: Vec<usize>
+}
+
+impl Sequence {
+    fn new(chunks:&[(Vec<u32>, u64)]) -> Self {
+        let len = chunks.iter().map(|(chunk, _)| chunk.len()).sum();
+        let mut seq = Sequence{
+            ids: Vec::with_capacity(len), prev: Vec::with_capacity(len), next: Vec::with_capacity(len),
+            weight: Vec::with_capacity(len), starts: Vec::with_capacity(chunks.len())
+        };
+        for (chunk, weight) in chunks {
+            seq.push(chunk, *weight);
+        }
+        seq
+    }
+
+    // the chunks are added as they're read so only one is ever in memory next to the sequence,
+    // encode turns the byte ids of a chunk into the ids training starts from
+    pub(crate) fn read(chunks:Chunks, mut encode: impl FnMut(Vec<u32>) -> Vec<u32>) -> Result<Self, TokenizerError> {
+        let mut seq = Sequence{ ids: Vec::new(), prev: Vec::new(), next: Vec::new(), weight: Vec::new(), starts: Vec::new() };
+        chunks.for_each(|chunk, weight| seq.push(&encode(chunk.iter().map(|b| u32::from(*b)).collect()), weight))?;
+        Ok(seq)
+    }
+
+    fn push(&mut self, chunk:&[u32], weight:u64) {
+        let start = self.ids.len();
+        self.starts.push(start);
+        for (i, id) in chunk.iter().enumerate() {
+            self.ids.push(*id);
+            self.prev.push(if i == 0 { NONE } else { start + i - 1 });
+            self.next.push(if i + 1 == chunk.len() { NONE } else { start + i + 1 });
+            self.weight.push(weight);
+        }
+    }
+
+    fn chunk_start(&self, pos:usize) -> usize {
+        self.starts[self.starts.partition_point(|start| *start <= pos) - 1]
+    }
+
+    // splits the sorted positions into up to threads groups that don't share a chunk,
+    // each with the part of the sequence it can change
+    fn split<'a>(&'a mut self, positions:&'a [usize], threads:usize) -> Vec<(Part<'a>, &'a [usize])> {
+        let mut cuts = vec![0];
+        for range in ranges(positions.len(), threads).into_iter().skip(1) {
+            let cut = self.chunk_start(positions[range.start]);
+            if cut > *cuts.last().unwrap() {
+                cuts.push(cut);
+            }
+        }
+
+        let mut groups = Vec::with_capacity(cuts.len());
+        let (mut ids, mut prev, mut next, mut weight) = (&mut self.ids[..], &mut self.prev[..], &mut self.next[..], &self.weight[..]);
+        let mut rest = positions;
+        for (i, offset) in cuts.iter().enumerate() {
+            let end = cuts.get(i + 1).map_or(ids.len(), |cut| cut - offset);
+            let (part_ids, ids_rest) = ids.split_at_mut(end);
+            let (part_prev, prev_rest) = prev.split_at_mut(end);
+            let (part_next, next_rest) = next.split_at_mut(end);
+            let (part_weight, weight_rest) = weight.split_at(end);
+            let (part_positions, positions_rest) = rest.split_at(rest.partition_point(|pos| *pos < offset + end));
+            groups.push((Part{ offset: *offset, ids: part_ids, prev: part_prev, next: part_next, weight: part_weight }, part_positions));
+            (ids, prev, next, weight, rest) = (ids_rest, prev_rest, next_rest, weight_rest, positions_rest);
+        }
+        groups
+    }
+}
+
+// a range of whole chunks of the sequence, prev/next still hold positions in the whole sequence
+struct Part<'a> {
+    offset: usize,
+    ids: &'a mut [u32],
+    prev: &'a mut [usize],
+    next: &'a mut [usize],
+    weight: &'a [u64]
+}
+
+// count changes and new positions of pairs, gathered per thread and added to PairStats after
+#[derive(Default)]
+struct Delta {
+    counts: HashMap<(u32, u32), i64>,
+    positions: HashMap<(u32, u32), Vec<usize>>
+}
+
+impl Delta {
+    fn add(&mut self, pair:(u32, u32), pos:usize, weight:u64) {
+        *self.counts.entry(pair).or_default() += weight as i64;
+        self.positions.entry(pair).or_default().push(pos);
+    }
+
+    fn remove(&mut self, pair:(u32, u32), weight:u64) {
+        *self.counts.entry(pair).or_default() -= weight as i64;
+    }
+}
+
+// replaces every occurrence of pair in positions with idx, positions are sorted so overlapping
+// pairs like (a, a) in "aaa" merge left to right the same way merge() does.
+// positions that don't hold the pair anymore are skipped
+fn apply_merge(part:Part, positions:&[usize], pair:(u32, u32), idx:u32) -> Delta {
+    let mut delta = Delta::default();
+    let local = |pos:usize| pos - part.offset;
+    for &pos in positions {
+        let right = part.next[local(pos)];
+        if part.ids[local(pos)] != pair.0 || right == NONE || part.ids[local(right)] != pair.1 {
+            continue;
+        }
+        let left = part.prev[local(pos)];
+        let after = part.next[local(right)];
+        let weight = part.weight[local(pos)];
+
+        if left != NONE {
+            let left_id = part.ids[local(left)];
+            delta.remove((left_id, pair.0), weight);
+            delta.add((left_id, idx), left, weight);
+        }
+        if after != NONE {
+            let after_id = part.ids[local(after)];
+            delta.remove((pair.1, after_id), weight);
+            delta.add((idx, after_id), pos, weight);
+        }
+        delta.remove(pair, weight);
+
+        part.ids[local(pos)] = idx;
+        part.ids[local(right)] = DEAD;
+        part.next[local(pos)] = after;
+        if after != NONE {
+            part.prev[local(after)] = pos;
+        }
+    }
+    delta
+}
+
+// pair counts with the positions each pair was seen at. positions aren't removed when a pair
+// disappears from them, they're checked when the pair gets merged. the heap is lazy the same way,
+// every count change pushes a new entry and entries that don't match the current count are skipped
+struct PairStats {
+    counts: HashMap<(u32, u32), u64>,
+    positions: HashMap<(u32, u32), Vec<usize>>,
+    heap: BinaryHeap<(u64, (u32, u32))>
+}
+
+impl PairStats {
+    fn new(seq:&Sequence, threads:usize) -> Self {
+        let deltas = parallel(ranges(seq.ids.len(), threads), |range| {
+            let mut delta = Delta::default();
+            for pos in range {
+                let next = seq.next[pos];
+                if next != NONE {
+                    delta.add((seq.ids[pos], seq.ids[next]), pos, seq.weight[pos]);
+                }
+            }
+            delta
+        });
+        let mut stats = PairStats{ counts: HashMap::new(), positions: HashMap::new(), heap: BinaryHeap::new() };
+        for delta in deltas {
+            stats.apply(delta);
+        }
+        for (pair, count) in stats.counts.iter() {
+            stats.heap.push((*count, *pair));
+        }
+        stats
+    }
+
+    // highest count first, ties go to the larger pair like frequent_pair does
+    fn pop(&mut self) -> Option<(u64, (u32, u32))> {
+        while let Some((count, pair)) = self.heap.pop() {
+            if count > 0 && self.counts.get(&pair) == Some(&count) {
+                return Some((count, pair));
+            }
+        }
+        None
+    }
+
+    fn apply(&mut self, delta:Delta) {
+        for (pair, change) in delta.counts {
+            let count = self.counts.entry(pair).or_default();
+            *count = (*count as i64 + change) as u64;
+        }
+        for (pair, positions) in delta.positions {
+            self.positions.entry(pair).or_default().extend(positions);
+        }
+    }
+}
+
+// learns up to num_merges merges over the chunks and their weights, new ids start at first_idx.
+// picks the same pair as recounting everything with frequent_pair each round would, on_merge
+// is called with each merge as it's learned. stops early once no pair occurs options.min_frequency
+// times. merges with at least options.parallel_min occurrences are applied on up to options.threads
+// threads, split by chunk
+pub fn learn_merges(chunks:&[(Vec<u32>, u64)], first_idx:u32, num_merges:u32, options:&TrainOptions, on_merge: impl FnMut(&Merge)) -> Vec<Merge> {
+    learn_sequence(Sequence::new(chunks), first_idx, num_merges, options, on_merge)
+}
+
+// learn_merges on chunks already in a sequence
+pub(crate) fn learn_sequence(mut seq:Sequence, first_idx:u32, num_merges:u32, options:&TrainOptions, mut on_merge: impl FnMut(&Merge)) -> Vec<Merge> {
+    let mut stats = PairStats::new(&seq, options.threads);
+    let mut merges = Vec::with_capacity(num_merges as usize);
+
+    for i in 0..num_merges {
+        let Some((count, pair)) = stats.pop() else { break };
+        if count < options.min_frequency {
+            break;
+        }
+        let idx = first_idx + i;
+
+        let mut positions = stats.positions.remove(&pair).unwrap_or_default();
+        positions.sort_unstable();
+        positions.dedup();
+
+        let threads = if positions.len() >= options.parallel_min { options.threads } else { 1 };
+        let deltas = parallel(seq.split(&positions, threads), |(part, positions)| apply_merge(part, positions, pair, idx));
+
+        let mut touched: Vec<(u32, u32)> = Vec::new();
+        for delta in deltas {
+            touched.extend(delta.counts.keys());
+            stats.apply(delta);
+        }
+        // sorted so the heap gets the same pushes whatever the thread count
+        touched.sort_unstable();
+        touched.dedup();
+        for changed in touched {
+            let count = stats.counts[&changed];
+            if count > 0 {
+                stats.heap.push((count, changed));
+            }
+        }
+
+        let merge = Merge{ pair, idx, count };
+        on_merge(&merge);
+        merges.push(merge);
+    }
+    merges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{count_pairs, merge, top_pairs, BasicTokenizer, Ordering};
+    use crate::corpus::{ChunkCounter, Fingerprint};
+
+    // recounts every pair each round, what training did before
+    fn naive(chunks:&[Vec<u32>], num_merges:u32) -> Vec<Merge> {
+        let mut chunks = chunks.to_vec();
+        let mut merges = Vec::new();
+        for i in 0..num_merges {
+            let mut stats = HashMap::new();
+            for chunk in chunks.iter() {
+                count_pairs(chunk.as_slice(), &mut stats);
+            }
+            let Some((count, pair)) = top_pairs(stats, 1, Ordering::Descending).first().map(|(count, pair)| (*count, (*pair.0, *pair.1))) else { break };
+            let idx = 256 + i;
+            chunks = chunks.iter().map(|chunk| merge(chunk, &pair, &idx)).collect();
+            merges.push(Merge{ pair, idx, count: count as u64 });
+        }
+        merges
+    }
+
+    #[test]
+    fn same_merges_as_recounting() {
+        // lots of ties and overlapping runs like "aaaa"
+        let text = "aaaa abab aaa bbbb abba aaaaa ab ba ba aab aabb aaabbb ababab ".repeat(3) + "zz zzz yzyz xyxy";
+        let chunks: Vec<Vec<u32>> = text.split(' ').map(|w| w.bytes().map(u32::from).collect()).collect();
+        let weighted: Vec<(Vec<u32>, u64)> = chunks.iter().map(|chunk| (chunk.clone(), 1)).collect();
+        let words: Vec<&str> = text.split(' ').collect();
+        for threads in [1, 3] {
+            // every merge on threads threads, however few occurrences it has
+            let options = TrainOptions{ threads, parallel_min: 1, ..TrainOptions::default() };
+            assert_eq!(learn_merges(&weighted, 256, 40, &options, |_| {}), naive(&chunks, 40));
+            // deduplicated chunks have to come out the same
+            let counted: Vec<(Vec<u32>, u64)> = count_chunks(&words, threads).into_iter().map(|(chunk, count)| (chunk.into_iter().map(u32::from).collect(), count)).collect();
+            assert_eq!(learn_merges(&counted, 256, 40, &options, |_| {}), naive(&chunks, 40));
+
+            let whole = vec![text.bytes().map(u32::from).collect::<Vec<u32>>()];
+            assert_eq!(learn_merges(&[(whole[0].clone(), 1)], 256, 60, &options, |_| {}), naive(&whole, 60));
+        }
+    }
+
+    #[test]
+    fn continuing_matches_training_further() {
+        let text = "the cat sat on the mat, the dog sat on the log. aaaa abab aaa bbbb abba ".repeat(5);
+        let chunks = || Chunks::Bytes(vec![(text.as_bytes().to_vec(), 1)]);
+        let options = TrainOptions{ threads: 1, ..TrainOptions::default() };
+        let full = BasicTokenizer::train_chunks(chunks(), 320, None, &options, Fingerprint::default()).unwrap();
+
+        let mut resumed = BasicTokenizer::train_chunks(chunks(), 290, None, &options, Fingerprint::default()).unwrap();
+        resumed.register_special_tokens(HashMap::from([("<|end|>".to_string(), 300)])).unwrap();
+        assert!(resumed.check_continue(320).is_err());
+        assert!(resumed.check_continue(300).is_ok());
+
+        resumed.special_tokens.clear();
+        resumed.check_continue(320).unwrap();
+        resumed.learn(chunks(), 320, None, &options, Fingerprint::default()).unwrap();
+        assert_eq!(resumed.merges, full.merges);
+        assert_eq!(resumed.vocab, full.vocab);
+    }
+
+    #[test]
+    fn spilled_chunks_train_like_chunks_in_memory() {
+        let text = "the cat sat on the mat, the dog sat on the log. aaaa abab aaa bbbb abba ".repeat(5);
+        let words: Vec<&str> = text.split(' ').collect();
+        let options = TrainOptions{ threads: 1, ..TrainOptions::default() };
+        let in_memory = BasicTokenizer::train_chunks(Chunks::Bytes(count_chunks(&words, 1)), 300, None, &options, Fingerprint::default()).unwrap();
+        for memory_limit in [usize::MAX, 1] {
+            let mut counter = ChunkCounter::new(memory_limit, None);
+            words.iter().try_for_each(|word| counter.add(word.as_bytes())).unwrap();
+            let counted = BasicTokenizer::train_chunks(Chunks::Counted(counter), 300, None, &options, Fingerprint::default()).unwrap();
+            assert_eq!(counted.merges, in_memory.merges);
+        }
+    }
+}
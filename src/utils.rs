@@ -20,3 +20,58 @@ where
     }
     heap.into_sorted_vec().into_iter().map(|r| r.0).collect()
 }
+
+// ascending keeps the k least frequent pairs, descending the k most frequent with the most frequent first
+pub enum Ordering {
+    Ascending,
+    Descending
+}
+
+// naive pair counting, training keeps its counts incrementally in train.rs and is checked against these
+pub fn frequent_pair<T>(array: &[T], k: usize, ordering:Ordering) -> Vec<(usize, (&T, &T))>
+where
+    T: Hash + Eq + Ord,
+{
+    let mut map: HashMap<(&T, &T), usize> = HashMap::new();
+    count_pairs(array, &mut map);
+    top_pairs(map, k, ordering)
+}
+
+// adds the consecutive pairs of array onto the counts in map, so counts can be gathered over many chunks
+pub fn count_pairs<'a, T>(array: &'a [T], map: &mut HashMap<(&'a T, &'a T), usize>)
+where
+    T: Hash + Eq + Ord,
+{
+    for pair in array.iter().zip(array.iter().skip(1)) {
+        *map.entry(pair).or_default() += 1;
+    }
+}
+
+// ties on count are broken by the pair itself, so the result doesn't depend on the hashmaps iteration order
+pub fn top_pairs<'a, T>(map: HashMap<(&'a T, &'a T), usize>, k: usize, ordering:Ordering) -> Vec<(usize, (&'a T, &'a T))>
+where
+    T: Hash + Eq + Ord,
+{
+    match ordering {
+        Ordering::Ascending => {
+            let mut heap = BinaryHeap::with_capacity(k + 1);
+            for (x, count) in map.into_iter() {
+                heap.push((count, x));
+                if heap.len() > k {
+                    heap.pop();
+                }
+            }
+            heap.into_sorted_vec().into_iter().collect()
+        },
+        Ordering::Descending => {
+            let mut heap = BinaryHeap::with_capacity(k + 1);
+            for (x, count) in map.into_iter() {
+                heap.push(Reverse((count, x)));
+                if heap.len() > k {
+                    heap.pop();
+                }
+            }
+            heap.into_sorted_vec().into_iter().map(|r| r.0).collect()
+        }
+    }
+}
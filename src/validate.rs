@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use crate::basic::BasicTokenizer;
+
+// everything inconsistent about the model, one message each, empty if there's nothing. the vocab is
+// rebuilt from the merges and compared with the one the model has. byte tokens are ids 0..256 unless
+// byte_ids is false, the GPT4Tokenizer's are shuffled
+pub(crate) fn problems(tokenizer:&BasicTokenizer, byte_ids:bool) -> Vec<String> {
+    let mut problems = Vec::new();
+    let show = |bytes:&[u8]| format!("{:?}", String::from_utf8_lossy(bytes));
+
+    if tokenizer.num_merges as usize != tokenizer.merges.len() {
+        problems.push(format!("num_merges is {} but there are {} merges", tokenizer.num_merges, tokenizer.merges.len()));
+    }
+    if tokenizer.vocab_size as usize != tokenizer.vocab.len() {
+        problems.push(format!("vocab_size is {} but the vocab has {} tokens", tokenizer.vocab_size, tokenizer.vocab.len()));
+    }
+    if let Some(params) = &tokenizer.params {
+        if params.vocab_size < tokenizer.vocab_size {
+            problems.push(format!("trained with vocab_size {} but the model has {} tokens", params.vocab_size, tokenizer.vocab_size));
+        }
+    }
+
+    // ids have to run from 0 without gaps
+    let mut ids: Vec<u32> = tokenizer.vocab.keys().copied().collect();
+    ids.sort();
+    let mut expected = 0;
+    for id in ids {
+        if id > expected {
+            problems.push(match id - expected {
+                1 => format!("vocab id {} is skipped", expected),
+                _ => format!("vocab ids {}..{} are skipped", expected, id - 1)
+            });
+        }
+        expected = id + 1;
+    }
+
+    // the byte tokens, then every merge in rank order on top of them
+    let mut built: HashMap<u32, Vec<u8>> = HashMap::new();
+    if byte_ids {
+        for b in 0..=255u8 {
+            match tokenizer.vocab.get(&u32::from(b)) {
+                Some(bytes) if *bytes == [b] => {},
+                Some(bytes) => problems.push(format!("vocab id {} is {} but ids below 256 have to be their own byte", b, show(bytes))),
+                None => problems.push(format!("byte {} has no token", b))
+            }
+            built.insert(u32::from(b), vec![b]);
+        }
+    } else {
+        built.extend(tokenizer.vocab.iter().filter(|(_, bytes)| bytes.len() == 1).map(|(id, bytes)| (*id, bytes.clone())));
+        for b in 0..=255u8 {
+            if !built.values().any(|bytes| *bytes == [b]) {
+                problems.push(format!("byte {} has no token", b));
+            }
+        }
+    }
+    let mut merges: Vec<(&(u32, u32), &u32)> = tokenizer.merges.iter().collect();
+    merges.sort_by_key(|(pair, idx)| (**idx, **pair));
+    let mut made_by: HashMap<u32, (u32, u32)> = HashMap::new();
+    for ((a, b), idx) in merges {
+        if let Some((c, d)) = made_by.insert(*idx, (*a, *b)) {
+            problems.push(format!("id {} is made by both merge ({},{}) and merge ({},{})", idx, c, d, a, b));
+            continue;
+        }
+        if built.contains_key(idx) {
+            problems.push(format!("merge ({},{}) makes id {} which is a byte token", a, b, idx));
+            continue;
+        }
+        let (Some(left), Some(right)) = (built.get(a).filter(|_| a < idx), built.get(b).filter(|_| b < idx)) else {
+            problems.push(format!("merge ({},{}) -> {} uses an id that isn't made before it", a, b, idx));
+            continue;
+        };
+        let bytes = [left.as_slice(), right.as_slice()].concat();
+        match tokenizer.vocab.get(idx) {
+            Some(token) if *token == bytes => {},
+            Some(token) => problems.push(format!("vocab id {} is {} but merge ({},{}) makes {}", idx, show(token), a, b, show(&bytes))),
+            None => problems.push(format!("merge ({},{}) makes id {} which isn't in the vocab", a, b, idx))
+        }
+        built.insert(*idx, bytes);
+    }
+    let mut unmade: Vec<(&u32, &Vec<u8>)> = tokenizer.vocab.iter().filter(|(id, _)| !built.contains_key(id)).collect();
+    unmade.sort();
+    for (id, bytes) in unmade {
+        problems.push(format!("vocab id {} ({}) is neither a byte nor made by a merge", id, show(bytes)));
+    }
+
+    // special tokens come after the vocab
+    let mut specials: Vec<(&String, &u32)> = tokenizer.special_tokens.iter().collect();
+    specials.sort_by_key(|(special, idx)| (**idx, *special));
+    for (i, (special, idx)) in specials.iter().enumerate() {
+        if special.is_empty() {
+            problems.push(format!("special token with id {} is empty", idx));
+        }
+        if tokenizer.vocab.contains_key(idx) || **idx < 256 + tokenizer.num_merges {
+            problems.push(format!("special token {:?} has id {} which is in the vocab's range", special, idx));
+        }
+        if let Some((other, _)) = specials[..i].iter().find(|(_, other_idx)| other_idx == idx) {
+            problems.push(format!("special tokens {:?} and {:?} both have id {}", other, special, idx));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn broken_models_list_every_problem() {
+        assert_eq!(problems(&testing::basic(270), true), Vec::<String>::new());
+
+        let mut edited = testing::basic(270);
+        edited.vocab.insert(260, b"oops".to_vec());
+        edited.vocab.remove(&265);
+        edited.merges.insert((300, 97), 270);
+        edited.special_tokens.insert("<|s|>".to_string(), 258);
+        let found = problems(&edited, true);
+        for expected in [
+            "num_merges is 14 but there are 15 merges",
+            "vocab_size is 270 but the vocab has 269 tokens",
+            "vocab id 265 is skipped",
+            "merge (300,97) -> 270 uses an id that isn't made before it",
+            "special token \"<|s|>\" has id 258 which is in the vocab's range"
+        ] {
+            assert!(found.iter().any(|problem| problem == expected), "{:?} not in {:?}", expected, found);
+        }
+        assert!(found.iter().any(|problem| problem.starts_with("vocab id 260 is \"oops\" but merge")), "{:?}", found);
+        assert!(found.iter().any(|problem| problem.starts_with("merge (") && problem.ends_with("makes id 265 which isn't in the vocab")), "{:?}", found);
+    }
+}